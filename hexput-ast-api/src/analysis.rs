@@ -0,0 +1,621 @@
+//! Static checks over a parsed program.
+//!
+//! `analyze` resolves names against the scopes the runtime creates for each
+//! block, loop iteration and callback call, and reports the mistakes it can
+//! find without running the program. The names the host provides are given
+//! in a `HostEnvironment`; checks that depend on names it leaves unknown are
+//! skipped rather than guessed. When it holds the signatures of the remote
+//! functions, the arguments of calls to them are checked as well.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+
+use crate::ast_structs::{Block, Expression, Program, SourceLocation, Statement};
+use crate::diagnostics::{Diagnostic, Severity};
+use crate::visitor::{self, Visitor};
+
+/// Functions the runtime provides to every program.
+const BUILTIN_FUNCTIONS: [&str; 1] = ["all"];
+
+/// The kinds of values a remote function takes or returns.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ValueType {
+    #[default]
+    Any,
+    String,
+    Number,
+    Boolean,
+    Null,
+    Array,
+    Object,
+}
+
+impl ValueType {
+    fn accepts(self, actual: ValueType) -> bool {
+        self == ValueType::Any || actual == ValueType::Any || self == actual
+    }
+}
+
+impl std::fmt::Display for ValueType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ValueType::Any => "any",
+            ValueType::String => "string",
+            ValueType::Number => "number",
+            ValueType::Boolean => "boolean",
+            ValueType::Null => "null",
+            ValueType::Array => "array",
+            ValueType::Object => "object",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ParameterSignature {
+    pub name: String,
+    #[serde(default, rename = "type")]
+    pub value_type: ValueType,
+    /// Optional parameters may only be followed by other optional ones.
+    #[serde(default)]
+    pub optional: bool,
+}
+
+/// A remote function as declared in a host's manifest.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FunctionSignature {
+    pub name: String,
+    /// `None` when the host does not declare the parameters, which skips
+    /// the argument checks.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Vec<ParameterSignature>>,
+    /// Whether any number of arguments may follow `params`.
+    #[serde(default)]
+    pub variadic: bool,
+    #[serde(default)]
+    pub returns: ValueType,
+    /// How long a runtime waits for a call to the function, in
+    /// milliseconds. Not used by the analysis.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+}
+
+impl FunctionSignature {
+    /// A signature that only declares the function exists.
+    pub fn named(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            params: None,
+            variadic: false,
+            returns: ValueType::Any,
+            timeout_ms: None,
+        }
+    }
+
+    /// Describes the accepted argument counts when `count` is not one of
+    /// them.
+    fn arity_mismatch(&self, count: usize) -> Option<String> {
+        let params = self.params.as_ref()?;
+        let required = params.iter().filter(|param| !param.optional).count();
+        if count >= required && (self.variadic || count <= params.len()) {
+            return None;
+        }
+
+        let expected = if self.variadic {
+            format!("at least {}", required)
+        } else if required == params.len() {
+            required.to_string()
+        } else {
+            format!("{} to {}", required, params.len())
+        };
+        let noun = if required == 1 && (self.variadic || params.len() == 1) { "argument" } else { "arguments" };
+        Some(format!(
+            "Function '{}' expects {} {}, but {} were provided",
+            self.name, expected, noun, count
+        ))
+    }
+}
+
+/// The context variables and remote functions available to a program.
+/// `None` means unknown.
+#[derive(Debug, Clone, Default)]
+pub struct HostEnvironment {
+    pub variables: Option<HashSet<String>>,
+    pub functions: Option<HashMap<String, FunctionSignature>>,
+}
+
+impl HostEnvironment {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_variables<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.variables = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Declares the remote functions by name only, without checking their
+    /// arguments.
+    pub fn with_functions<I, S>(self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.with_signatures(names.into_iter().map(FunctionSignature::named))
+    }
+
+    pub fn with_signatures(mut self, signatures: impl IntoIterator<Item = FunctionSignature>) -> Self {
+        self.functions = Some(
+            signatures
+                .into_iter()
+                .map(|signature| (signature.name.clone(), signature))
+                .collect(),
+        );
+        self
+    }
+
+    fn provides_variable(&self, name: &str) -> bool {
+        self.variables.as_ref().is_some_and(|names| names.contains(name))
+    }
+
+    fn provides_function(&self, name: &str) -> bool {
+        self.signature(name).is_some()
+    }
+
+    fn signature(&self, name: &str) -> Option<&FunctionSignature> {
+        self.functions.as_ref()?.get(name)
+    }
+}
+
+/// Checks `program`, which should come from `parse_code`: the optimizer
+/// already drops the unreachable statements this reports.
+pub fn analyze(program: &Program, host: &HostEnvironment) -> Vec<Diagnostic> {
+    let mut analyzer = Analyzer {
+        host,
+        scopes: Vec::new(),
+        frame: 0,
+        diagnostics: Vec::new(),
+    };
+    analyzer.visit_program(program);
+
+    let mut diagnostics = analyzer.diagnostics;
+    diagnostics.sort_by_key(|d| (d.location.start_line, d.location.start_column));
+    diagnostics
+}
+
+#[derive(Default)]
+struct Scope {
+    variables: HashMap<String, Binding>,
+    callbacks: HashSet<String>,
+    /// Names declared by statements of the scope that have not run yet.
+    later_variables: HashSet<String>,
+    later_callbacks: HashSet<String>,
+    /// Later variables read by a callback body, which runs after them.
+    late_reads: HashSet<String>,
+}
+
+struct Binding {
+    /// Where a `vl` declared the variable; `None` for parameters and loop
+    /// variables, which are never reported as unused.
+    declaration: Option<SourceLocation>,
+    used: bool,
+}
+
+enum Lookup {
+    Found,
+    /// Only declared by a statement after the reference.
+    Later,
+    Missing,
+}
+
+struct Analyzer<'h> {
+    host: &'h HostEnvironment,
+    scopes: Vec<Scope>,
+    /// Index of the scope of the innermost callback body. Callbacks run when
+    /// called, so the scopes below it count their later names as declared.
+    frame: usize,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Analyzer<'_> {
+    fn report(&mut self, severity: Severity, code: &str, message: String, location: SourceLocation) {
+        self.diagnostics.push(Diagnostic {
+            severity,
+            code: code.to_string(),
+            message,
+            location,
+        });
+    }
+
+    fn scope(&mut self) -> &mut Scope {
+        self.scopes.last_mut().expect("analysis always runs inside a scope")
+    }
+
+    /// Runs the statements of a block in a new scope that starts with
+    /// `bindings` declared.
+    fn scoped<'a>(&mut self, statements: &[Statement], bindings: impl IntoIterator<Item = &'a String>) {
+        let mut scope = Scope::default();
+        for name in bindings {
+            scope.variables.insert(name.clone(), Binding { declaration: None, used: true });
+        }
+        self.scopes.push(scope);
+
+        self.statements(statements);
+
+        let scope = self.scopes.pop().expect("scope pushed above");
+        for (name, binding) in scope.variables {
+            if let (Some(location), false) = (binding.declaration, binding.used) {
+                self.report(
+                    Severity::Warning,
+                    "UnusedVariable",
+                    format!("Variable '{}' is declared but never used", name),
+                    location,
+                );
+            }
+        }
+    }
+
+    fn callback_body(&mut self, params: &[String], body: &Block, location: SourceLocation) {
+        let mut seen = HashSet::new();
+        for param in params {
+            if !seen.insert(param) {
+                self.report(
+                    Severity::Error,
+                    "DuplicateParameter",
+                    format!("Duplicate parameter '{}'", param),
+                    location,
+                );
+            }
+        }
+
+        let frame = std::mem::replace(&mut self.frame, self.scopes.len());
+        self.scoped(&body.statements, params);
+        self.frame = frame;
+    }
+
+    fn statements(&mut self, statements: &[Statement]) {
+        let exit = statements.iter().position(|stmt| matches!(
+            stmt,
+            Statement::ReturnStatement { .. } | Statement::EndStatement { .. } | Statement::ContinueStatement { .. }
+        ));
+        let reachable = match exit {
+            Some(index) => &statements[..=index],
+            None => statements,
+        };
+
+        if let Some(unreachable) = statements.get(reachable.len()) {
+            let keyword = match reachable.last() {
+                Some(Statement::ReturnStatement { .. }) => "res",
+                Some(Statement::EndStatement { .. }) => "end",
+                _ => "continue",
+            };
+            self.report(
+                Severity::Warning,
+                "UnreachableCode",
+                format!("Unreachable code after '{}'", keyword),
+                unreachable.location(),
+            );
+        }
+
+        let scope = self.scope();
+        for statement in reachable {
+            match statement {
+                Statement::VariableDeclaration { name, .. } => {
+                    scope.later_variables.insert(name.clone());
+                },
+                Statement::CallbackDeclaration { name, .. } => {
+                    scope.later_callbacks.insert(name.clone());
+                },
+                _ => {},
+            }
+        }
+
+        for statement in reachable {
+            self.visit_statement(statement);
+        }
+    }
+
+    fn lookup_callback(&self, name: &str) -> Lookup {
+        let mut later = false;
+        for (index, scope) in self.scopes.iter().enumerate().rev() {
+            if scope.callbacks.contains(name) {
+                return Lookup::Found;
+            }
+            if scope.later_callbacks.contains(name) {
+                if index < self.frame {
+                    return Lookup::Found;
+                }
+                later = true;
+            }
+        }
+        if later { Lookup::Later } else { Lookup::Missing }
+    }
+
+    fn lookup_variable(&mut self, name: &str, read: bool) -> Lookup {
+        let frame = self.frame;
+        let mut later = false;
+        for (index, scope) in self.scopes.iter_mut().enumerate().rev() {
+            if let Some(binding) = scope.variables.get_mut(name) {
+                binding.used |= read;
+                return Lookup::Found;
+            }
+            if scope.later_variables.contains(name) {
+                if index < frame {
+                    if read {
+                        scope.late_reads.insert(name.to_string());
+                    }
+                    return Lookup::Found;
+                }
+                later = true;
+            }
+        }
+        if later { Lookup::Later } else { Lookup::Missing }
+    }
+
+    fn declare_variable(&mut self, name: &str, location: SourceLocation) {
+        let scope = self.scope();
+        let used = scope.late_reads.contains(name);
+        scope.variables
+            .entry(name.to_string())
+            .or_insert(Binding { declaration: Some(location), used });
+    }
+
+    fn identifier(&mut self, name: &str, location: SourceLocation) {
+        // Callbacks shadow variables, as in the runtime.
+        let callback = self.lookup_callback(name);
+        if matches!(callback, Lookup::Found) {
+            return;
+        }
+
+        match (self.lookup_variable(name, true), callback) {
+            (Lookup::Found, _) => {},
+            _ if self.host.provides_variable(name) => {},
+            (Lookup::Later, _) | (_, Lookup::Later) => self.report(
+                Severity::Error,
+                "UseBeforeDeclaration",
+                format!("Variable '{}' is used before it is declared", name),
+                location,
+            ),
+            (Lookup::Missing, _) => {
+                if self.host.variables.is_some() {
+                    self.report(
+                        Severity::Error,
+                        "UndefinedVariable",
+                        format!("Undefined variable: {}", name),
+                        location,
+                    );
+                }
+            },
+        }
+    }
+
+    fn assignment(&mut self, target: &str, location: SourceLocation) {
+        match self.lookup_variable(target, false) {
+            Lookup::Found => {},
+            _ if self.host.provides_variable(target) => {},
+            Lookup::Later => self.report(
+                Severity::Error,
+                "UseBeforeDeclaration",
+                format!("Variable '{}' is assigned before it is declared", target),
+                location,
+            ),
+            Lookup::Missing => {
+                if self.host.variables.is_some() {
+                    self.report(
+                        Severity::Error,
+                        "UndefinedVariable",
+                        format!("Cannot assign to undeclared variable: {}", target),
+                        location,
+                    );
+                }
+            },
+        }
+    }
+
+    fn call(&mut self, callee: &str, arguments: &[Expression], location: SourceLocation) {
+        match self.lookup_callback(callee) {
+            Lookup::Found => {},
+            _ if BUILTIN_FUNCTIONS.contains(&callee) => {},
+            _ if self.host.provides_function(callee) => self.arguments(callee, arguments, location),
+            Lookup::Later => self.report(
+                Severity::Error,
+                "UseBeforeDeclaration",
+                format!("Callback '{}' is called before it is declared", callee),
+                location,
+            ),
+            Lookup::Missing => {
+                if self.host.functions.is_some() {
+                    self.report(
+                        Severity::Error,
+                        "UnknownFunction",
+                        format!("Function '{}' not found", callee),
+                        location,
+                    );
+                }
+            },
+        }
+    }
+
+    /// Checks the arguments of a call to a remote function against its
+    /// signature.
+    fn arguments(&mut self, callee: &str, arguments: &[Expression], location: SourceLocation) {
+        let Some(signature) = self.host.signature(callee) else {
+            return;
+        };
+
+        if let Some(message) = signature.arity_mismatch(arguments.len()) {
+            self.report(Severity::Error, "ArgumentCountMismatch", message, location);
+            return;
+        }
+
+        let params = signature.params.iter().flatten();
+        let mismatches: Vec<_> = params
+            .zip(arguments)
+            .enumerate()
+            .filter_map(|(index, (param, argument))| {
+                let actual = self.value_type(argument);
+                (!param.value_type.accepts(actual)).then(|| (
+                    format!(
+                        "Argument {} of '{}' must be {}, got {}",
+                        index + 1,
+                        callee,
+                        param.value_type,
+                        actual
+                    ),
+                    argument.location(),
+                ))
+            })
+            .collect();
+
+        for (message, location) in mismatches {
+            self.report(Severity::Error, "ArgumentTypeMismatch", message, location);
+        }
+    }
+
+    /// The type of `expression` when it is known without running it.
+    fn value_type(&self, expression: &Expression) -> ValueType {
+        match expression {
+            Expression::StringLiteral { .. } => ValueType::String,
+            Expression::NumberLiteral { .. } => ValueType::Number,
+            Expression::BooleanLiteral { .. } => ValueType::Boolean,
+            Expression::NullLiteral { .. } => ValueType::Null,
+            Expression::ArrayExpression { .. } | Expression::KeysOfExpression { .. } => ValueType::Array,
+            Expression::ObjectExpression { .. } => ValueType::Object,
+            Expression::CallExpression { callee, .. } if matches!(self.lookup_callback(callee), Lookup::Missing) => {
+                self.host.signature(callee).map_or(ValueType::Any, |signature| signature.returns)
+            },
+            _ => ValueType::Any,
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for Analyzer<'_> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        self.scoped(&program.statements, []);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        self.scoped(&block.statements, []);
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        match statement {
+            Statement::VariableDeclaration { name, value, location, .. } => {
+                self.visit_expression(value);
+                self.declare_variable(name, *location);
+            },
+            Statement::CallbackDeclaration { name, params, body, location, .. } => {
+                self.scope().callbacks.insert(name.clone());
+                self.callback_body(params, body, *location);
+            },
+            Statement::LoopStatement { key_variable, variable, iterable, body, .. } => {
+                self.visit_expression(iterable);
+                self.scoped(&body.statements, key_variable.iter().chain([variable]));
+            },
+            _ => visitor::walk_statement(self, statement),
+        }
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        match expression {
+            Expression::Identifier { name, location } => self.identifier(name, *location),
+            Expression::AssignmentExpression { target, value, location } => {
+                self.visit_expression(value);
+                self.assignment(target, *location);
+            },
+            Expression::CallExpression { callee, arguments, location } => {
+                self.call(callee, arguments, *location);
+                for argument in arguments {
+                    self.visit_expression(argument);
+                }
+            },
+            Expression::InlineCallbackExpression { name, params, body, location, .. } => {
+                self.scope().callbacks.insert(name.clone());
+                self.callback_body(params, body, *location);
+            },
+            _ => visitor::walk_expression(self, expression),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature_flags::FeatureFlags;
+
+    fn codes(code: &str, host: &HostEnvironment) -> Vec<(String, usize)> {
+        let program = crate::parse_code(code, FeatureFlags::default()).unwrap();
+        analyze(&program, host)
+            .into_iter()
+            .map(|d| (d.code, d.location.start_line))
+            .collect()
+    }
+
+    #[test]
+    fn reports_names_the_host_does_not_provide() {
+        let code = "vl a = input;\nres fetch(a, other) + lookup();";
+        let host = HostEnvironment::new()
+            .with_variables(["input"])
+            .with_functions(["fetch"]);
+
+        assert_eq!(codes(code, &host), [
+            ("UndefinedVariable".to_string(), 2),
+            ("UnknownFunction".to_string(), 2),
+        ]);
+        assert!(codes(code, &HostEnvironment::new()).is_empty());
+    }
+
+    #[test]
+    fn warns_about_unused_variables_and_unreachable_code() {
+        let code = "vl unused = 1;\ncb f(x) {\n    res x;\n    print(x);\n}\nres f(2);";
+
+        assert_eq!(codes(code, &HostEnvironment::new()), [
+            ("UnusedVariable".to_string(), 1),
+            ("UnreachableCode".to_string(), 4),
+        ]);
+    }
+
+    #[test]
+    fn callbacks_see_names_declared_after_them() {
+        let host = HostEnvironment::new().with_variables(Vec::<String>::new());
+
+        assert_eq!(codes("print(later);\nvl later = 1;\nprint(later);", &host), [
+            ("UseBeforeDeclaration".to_string(), 1),
+        ]);
+        assert!(codes("cb f() { res later; }\nvl later = 1;\nres f();", &host).is_empty());
+    }
+
+    #[test]
+    fn checks_calls_against_function_signatures() {
+        let manifest = r#"[
+            { "name": "fetch", "params": [{ "name": "url", "type": "string" }, { "name": "retries", "type": "number", "optional": true }], "returns": "object" },
+            { "name": "log", "variadic": true, "params": [] },
+            { "name": "save" }
+        ]"#;
+        let signatures: Vec<FunctionSignature> = serde_json::from_str(manifest).unwrap();
+        let host = HostEnvironment::new().with_signatures(signatures);
+        let diagnostics = |code: &str| {
+            let program = crate::parse_code(code, FeatureFlags::default()).unwrap();
+            analyze(&program, &host)
+                .into_iter()
+                .map(|d| (d.code, d.message))
+                .collect::<Vec<_>>()
+        };
+
+        assert!(diagnostics("vl r = fetch(\"a\", 2);\nlog(r, 1, true);\nsave(1, 2, 3);").is_empty());
+        assert_eq!(diagnostics("fetch();"), [(
+            "ArgumentCountMismatch".to_string(),
+            "Function 'fetch' expects 1 to 2 arguments, but 0 were provided".to_string(),
+        )]);
+        assert_eq!(diagnostics("fetch(1, fetch(\"b\"));"), [
+            ("ArgumentTypeMismatch".to_string(), "Argument 1 of 'fetch' must be string, got number".to_string()),
+            ("ArgumentTypeMismatch".to_string(), "Argument 2 of 'fetch' must be number, got object".to_string()),
+        ]);
+    }
+}
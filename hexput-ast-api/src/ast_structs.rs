@@ -1,6 +1,8 @@
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize)]
+/// Where a node starts and ends in the source. Defaults to all zeros for
+/// nodes loaded from JSON written without source mapping.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
 pub struct SourceLocation {
     pub start_line: usize,
     pub start_column: usize,
@@ -26,6 +28,10 @@ impl SourceLocation {
 }
 
 
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
 fn get_line_column(source: &str, offset: usize) -> (usize, usize) {
     let mut line = 1;
     let mut column = 1;
@@ -47,17 +53,22 @@ fn get_line_column(source: &str, offset: usize) -> (usize, usize) {
 }
 
 
-#[derive(Debug, Clone, Copy, Serialize)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Program {
     #[serde(rename = "type")]
     pub node_type: String,
     pub statements: Vec<Statement>,
+    /// Comments after the last statement, only kept when parsing with
+    /// comments.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing_comments: Vec<Comment>,
+    #[serde(default)]
     pub location: SourceLocation,
 }
 
@@ -66,23 +77,30 @@ impl Program {
         Self {
             node_type: "PROGRAM".to_string(),
             statements,
+            trailing_comments: Vec::new(),
             location,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Statement {
     #[serde(rename = "VARIABLE_DECLARATION")]
     VariableDeclaration {
         name: String,
         value: Expression,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "EXPRESSION_STATEMENT")]
     ExpressionStatement {
         expression: Expression,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "IF_STATEMENT")]
@@ -91,47 +109,137 @@ pub enum Statement {
         body: Block,
         #[serde(skip_serializing_if = "Option::is_none")]
         else_body: Option<Block>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "BLOCK")]
     Block { 
         block: Block,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "CALLBACK_DECLARATION")]
     CallbackDeclaration {
         name: String,
+        /// Set when the source gave no name and `name` was generated.
+        #[serde(default, skip_serializing_if = "is_false")]
+        anonymous: bool,
         params: Vec<String>,
         body: Block,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "RETURN_STATEMENT")]
     ReturnStatement {
         value: Expression,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "LOOP_STATEMENT")]
     LoopStatement {
+        /// Bound to the key or index in `loop k, v in x`.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        key_variable: Option<String>,
         variable: String,
         iterable: Expression,
         body: Block,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
+        location: SourceLocation,
+    },
+    #[serde(rename = "WHILE_LOOP_STATEMENT")]
+    WhileLoopStatement {
+        condition: Expression,
+        body: Block,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "END_STATEMENT")]
     EndStatement {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "CONTINUE_STATEMENT")]
     ContinueStatement {
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        comments: Vec<Comment>,
+        #[serde(default)]
         location: SourceLocation,
     },
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl Statement {
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Statement::VariableDeclaration { location, .. } |
+            Statement::ExpressionStatement { location, .. } |
+            Statement::IfStatement { location, .. } |
+            Statement::Block { location, .. } |
+            Statement::CallbackDeclaration { location, .. } |
+            Statement::ReturnStatement { location, .. } |
+            Statement::LoopStatement { location, .. } |
+            Statement::WhileLoopStatement { location, .. } |
+            Statement::EndStatement { location, .. } |
+            Statement::ContinueStatement { location, .. } => *location,
+        }
+    }
+
+    /// The comments directly before the statement. Only filled when parsing
+    /// with comments; otherwise empty.
+    pub fn comments(&self) -> &[Comment] {
+        match self {
+            Statement::VariableDeclaration { comments, .. } |
+            Statement::ExpressionStatement { comments, .. } |
+            Statement::IfStatement { comments, .. } |
+            Statement::Block { comments, .. } |
+            Statement::CallbackDeclaration { comments, .. } |
+            Statement::ReturnStatement { comments, .. } |
+            Statement::LoopStatement { comments, .. } |
+            Statement::WhileLoopStatement { comments, .. } |
+            Statement::EndStatement { comments, .. } |
+            Statement::ContinueStatement { comments, .. } => comments,
+        }
+    }
+
+    pub fn comments_mut(&mut self) -> &mut Vec<Comment> {
+        match self {
+            Statement::VariableDeclaration { comments, .. } |
+            Statement::ExpressionStatement { comments, .. } |
+            Statement::IfStatement { comments, .. } |
+            Statement::Block { comments, .. } |
+            Statement::CallbackDeclaration { comments, .. } |
+            Statement::ReturnStatement { comments, .. } |
+            Statement::LoopStatement { comments, .. } |
+            Statement::WhileLoopStatement { comments, .. } |
+            Statement::EndStatement { comments, .. } |
+            Statement::ContinueStatement { comments, .. } => comments,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     #[serde(rename = "type")]
     pub node_type: String,
     pub statements: Vec<Statement>,
+    /// Comments after the last statement, only kept when parsing with
+    /// comments.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trailing_comments: Vec<Comment>,
+    #[serde(default)]
     pub location: SourceLocation,
 }
 
@@ -140,27 +248,31 @@ impl Block {
         Self {
             node_type: "BLOCK".to_string(),
             statements,
+            trailing_comments: Vec::new(),
             location,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum Expression {
     #[serde(rename = "STRING_LITERAL")]
     StringLiteral {
         value: String,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "NUMBER_LITERAL")]
     NumberLiteral {
         value: f64,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "IDENTIFIER")]
     Identifier {
         name: String,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "BINARY_EXPRESSION")]
@@ -168,12 +280,14 @@ pub enum Expression {
         left: Box<Expression>,
         operator: Operator,
         right: Box<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "ASSIGNMENT_EXPRESSION")]
     AssignmentExpression {
         target: String,
         value: Box<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "MEMBER_ASSIGNMENT_EXPRESSION")]
@@ -185,12 +299,14 @@ pub enum Expression {
         property_expr: Option<Box<Expression>>,
         computed: bool,  
         value: Box<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "CALL_EXPRESSION")]
     CallExpression {
         callee: String,
         arguments: Vec<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "MEMBER_CALL_EXPRESSION")]
@@ -202,23 +318,30 @@ pub enum Expression {
         property_expr: Option<Box<Expression>>,
         computed: bool,
         arguments: Vec<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "INLINE_CALLBACK_EXPRESSION")]
     InlineCallbackExpression {
         name: String,
+        /// Set when the source gave no name and `name` was generated.
+        #[serde(default, skip_serializing_if = "is_false")]
+        anonymous: bool,
         params: Vec<String>,
         body: Block,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "ARRAY_EXPRESSION")]
     ArrayExpression {
         elements: Vec<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "OBJECT_EXPRESSION")]
     ObjectExpression {
         properties: Vec<Property>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "MEMBER_EXPRESSION")]
@@ -229,36 +352,102 @@ pub enum Expression {
         #[serde(skip_serializing_if = "Option::is_none")]
         property_expr: Option<Box<Expression>>,
         computed: bool,  
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "KEYS_OF_EXPRESSION")]
     KeysOfExpression {
         object: Box<Expression>,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "BOOLEAN_LITERAL")]
     BooleanLiteral {
         value: bool,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "UNARY_EXPRESSION")]
     UnaryExpression {
         operator: UnaryOperator,
         operand: Box<Expression>,
+        #[serde(default)]
+        location: SourceLocation,
+    },
+    #[serde(rename = "RANGE_EXPRESSION")]
+    RangeExpression {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        step: Option<Box<Expression>>,
+        inclusive: bool,
+        #[serde(default)]
         location: SourceLocation,
     },
     #[serde(rename = "NULL_LITERAL")]
     NullLiteral {
+        #[serde(default)]
+        location: SourceLocation,
+    },
+}
+
+impl Expression {
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Expression::StringLiteral { location, .. } |
+            Expression::NumberLiteral { location, .. } |
+            Expression::Identifier { location, .. } |
+            Expression::BinaryExpression { location, .. } |
+            Expression::AssignmentExpression { location, .. } |
+            Expression::MemberAssignmentExpression { location, .. } |
+            Expression::CallExpression { location, .. } |
+            Expression::MemberCallExpression { location, .. } |
+            Expression::InlineCallbackExpression { location, .. } |
+            Expression::ArrayExpression { location, .. } |
+            Expression::ObjectExpression { location, .. } |
+            Expression::MemberExpression { location, .. } |
+            Expression::KeysOfExpression { location, .. } |
+            Expression::BooleanLiteral { location, .. } |
+            Expression::UnaryExpression { location, .. } |
+            Expression::RangeExpression { location, .. } |
+            Expression::NullLiteral { location } => *location,
+        }
+    }
+}
+
+/// A `// line` or `/* block */` comment. `text` excludes the delimiters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Comment {
+    #[serde(rename = "LINE_COMMENT")]
+    Line {
+        text: String,
+        #[serde(default)]
+        location: SourceLocation,
+    },
+    #[serde(rename = "BLOCK_COMMENT")]
+    Block {
+        text: String,
+        #[serde(default)]
         location: SourceLocation,
     },
 }
 
-#[derive(Debug, Clone, Serialize)]
+impl Comment {
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            Comment::Line { location, .. } | Comment::Block { location, .. } => *location,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Property {
     #[serde(rename = "type")]
     pub node_type: String,
     pub key: String,
     pub value: Expression,
+    #[serde(default)]
     pub location: SourceLocation,
 }
 
@@ -273,7 +462,7 @@ impl Property {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum Operator {
     Equal,
     NotEqual,
@@ -281,6 +470,9 @@ pub enum Operator {
     Minus,
     Multiply,
     Divide,
+    Modulo,
+    Power,
+    IntegerDivide,
     Greater,
     Less,
     GreaterEqual,
@@ -289,8 +481,10 @@ pub enum Operator {
     Or,
 }
 
-#[derive(Debug, Clone, PartialEq, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub enum UnaryOperator {
     Not,
+    Negate,
+    Plus,
 }
 
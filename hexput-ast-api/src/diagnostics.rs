@@ -0,0 +1,30 @@
+use crate::ast_structs::SourceLocation;
+use crate::parser::ParseError;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+/// A problem found in the source, reported without stopping at the first one.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: String,
+    pub message: String,
+    pub location: SourceLocation,
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic {
+            severity: Severity::Error,
+            code: error.kind().to_string(),
+            message: error.message(),
+            location: error.location(),
+        }
+    }
+}
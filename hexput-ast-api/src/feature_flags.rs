@@ -1,4 +1,7 @@
 use serde::Serialize;
+use crate::ast_structs::{Expression, Program, Statement};
+use crate::parser::ParseError;
+use crate::visitor::{self, Visitor};
 
 
 #[derive(Debug, Clone, Copy, Serialize)]
@@ -6,6 +9,7 @@ pub struct FeatureFlags {
     pub allow_variable_declaration: bool,
     pub allow_conditionals: bool,
     pub allow_loops: bool,
+    pub allow_while_loops: bool,
     pub allow_callbacks: bool,
     pub allow_return_statements: bool,
     pub allow_loop_control: bool,
@@ -23,6 +27,7 @@ impl Default for FeatureFlags {
             allow_variable_declaration: true,
             allow_conditionals: true,
             allow_loops: true,
+            allow_while_loops: true,
             allow_callbacks: true,
             allow_return_statements: true,
             allow_loop_control: true,
@@ -46,6 +51,7 @@ impl FeatureFlags {
             allow_variable_declaration: false,
             allow_conditionals: false,
             allow_loops: false,
+            allow_while_loops: false,
             allow_callbacks: false,
             allow_return_statements: false,
             allow_loop_control: false,
@@ -65,4 +71,139 @@ impl FeatureFlags {
         flags
     }
 
+    /// Checks an already parsed program against the flags, for programs that
+    /// did not come from the parser with these flags, such as an AST sent to
+    /// be executed. Returns the same error the parser gives for the first
+    /// disabled feature found.
+    pub fn check_program(&self, program: &Program) -> Result<(), ParseError> {
+        let mut check = FeatureCheck { flags: *self, error: None };
+        check.visit_program(program);
+        
+        match check.error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+}
+
+struct FeatureCheck {
+    flags: FeatureFlags,
+    error: Option<ParseError>,
+}
+
+impl FeatureCheck {
+    fn statement_feature(&self, statement: &Statement) -> Option<&'static str> {
+        let flags = &self.flags;
+        match statement {
+            Statement::VariableDeclaration { .. } if !flags.allow_variable_declaration => Some("Variable declarations"),
+            Statement::IfStatement { .. } if !flags.allow_conditionals => Some("Conditional statements"),
+            Statement::LoopStatement { .. } if !flags.allow_loops => Some("Loop statements"),
+            Statement::WhileLoopStatement { .. } if !flags.allow_loops => Some("Loop statements"),
+            Statement::WhileLoopStatement { .. } if !flags.allow_while_loops => Some("Conditional loops"),
+            Statement::CallbackDeclaration { .. } if !flags.allow_callbacks => Some("Callback declarations"),
+            Statement::ReturnStatement { .. } if !flags.allow_return_statements => Some("Return statements"),
+            Statement::EndStatement { .. } if !flags.allow_loop_control => Some("Loop control statements (end)"),
+            Statement::ContinueStatement { .. } if !flags.allow_loop_control => Some("Loop control statements (continue)"),
+            _ => None,
+        }
+    }
+    
+    fn expression_feature(&self, expression: &Expression) -> Option<&'static str> {
+        let flags = &self.flags;
+        match expression {
+            Expression::MemberExpression { computed, .. } |
+            Expression::MemberCallExpression { computed, .. } |
+            Expression::MemberAssignmentExpression { computed, .. } if !flags.allow_object_navigation => {
+                if *computed {
+                    Some("Object navigation (bracket notation)")
+                } else {
+                    Some("Object navigation (dot notation)")
+                }
+            },
+            Expression::AssignmentExpression { .. } |
+            Expression::MemberAssignmentExpression { .. } if !flags.allow_assignments => Some("Assignments"),
+            Expression::InlineCallbackExpression { .. } if !flags.allow_callbacks => Some("Inline callback expressions"),
+            Expression::ArrayExpression { .. } if !flags.allow_array_constructions => Some("Array literals"),
+            Expression::ObjectExpression { .. } if !flags.allow_object_constructions => Some("Object literals"),
+            Expression::KeysOfExpression { .. } if !flags.allow_object_keys => Some("Object keys operator (keysof)"),
+            _ => None,
+        }
+    }
+}
+
+impl<'ast> Visitor<'ast> for FeatureCheck {
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(feature) = self.statement_feature(statement) {
+            self.error = Some(ParseError::FeatureDisabled(feature.to_string(), statement.location()));
+            return;
+        }
+        visitor::walk_statement(self, statement);
+    }
+    
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Some(feature) = self.expression_feature(expression) {
+            self.error = Some(ParseError::FeatureDisabled(feature.to_string(), expression.location()));
+            return;
+        }
+        visitor::walk_expression(self, expression);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(code: &str, flags: FeatureFlags) -> Result<Program, ParseError> {
+        let tokens = crate::lexer::tokenize(code)?;
+        crate::parser::Parser::new(&tokens, flags, code).parse_program()
+    }
+
+    fn disabled_feature(error: ParseError) -> String {
+        match error {
+            ParseError::FeatureDisabled(feature, _) => feature,
+            other => panic!("expected a disabled feature, got {:?}", other),
+        }
+    }
+
+    fn assert_rejected_like_parser(code: &str, disable: fn(&mut FeatureFlags)) {
+        let mut flags = FeatureFlags::default();
+        disable(&mut flags);
+        let program = parse(code, FeatureFlags::default()).unwrap();
+        let parse_error = parse(code, flags).unwrap_err();
+
+        assert_eq!(
+            disabled_feature(flags.check_program(&program).unwrap_err()),
+            disabled_feature(parse_error),
+            "for {}", code
+        );
+    }
+
+    #[test]
+    fn rejects_what_the_parser_rejects() {
+        assert_rejected_like_parser("vl a = 1;", |f| f.allow_variable_declaration = false);
+        assert_rejected_like_parser("loop i in items { }", |f| f.allow_loops = false);
+        assert_rejected_like_parser("loop while x { }", |f| f.allow_while_loops = false);
+        assert_rejected_like_parser("run([1], cb (x) { res x; });", |f| f.allow_callbacks = false);
+        assert_rejected_like_parser("if x { res 1; }", |f| f.allow_return_statements = false);
+        assert_rejected_like_parser("a.b = 1;", |f| f.allow_object_navigation = false);
+        assert_rejected_like_parser("a.b = 1;", |f| f.allow_assignments = false);
+        assert_rejected_like_parser("res items[0];", |f| f.allow_object_navigation = false);
+        assert_rejected_like_parser("res keysof {};", |f| f.allow_object_keys = false);
+    }
+
+    #[test]
+    fn accepts_programs_within_the_flags() {
+        let program = parse("vl a = 1 + 2; res a;", FeatureFlags::default()).unwrap();
+        let mut flags = FeatureFlags::all_disabled();
+        flags.allow_variable_declaration = true;
+        flags.allow_return_statements = true;
+
+        assert!(flags.check_program(&program).is_ok());
+    }
 }
@@ -1,5 +1,6 @@
 use logos::{Logos, Lexer, Span};
-use crate::ast_structs::SourceLocation;
+use crate::ast_structs::{Comment, SourceLocation};
+use crate::parser::ParseError;
 
 #[derive(Logos, Debug, PartialEq, Clone)]
 #[logos(skip r"[ \t\n\f]+", error = TokenError)]
@@ -26,6 +27,9 @@ pub enum Token {
     #[token("in")]
     In,
     
+    #[token("while")]
+    While,
+    
     #[token("end")]
     End,
     
@@ -49,15 +53,20 @@ pub enum Token {
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_owned(), priority = 2)]
     Identifier(String),
 
-    // Comments
-    #[regex(r"//[^\n]*", logos::skip)]
-    Comment,
+    // Comments, split off from the other tokens by `tokenize`
+    #[regex(r"//[^\n]*", |lex| lex.slice()[2..].to_owned())]
+    LineComment(String),
+    
+    #[token("/*", block_comment)]
+    BlockComment(String),
     
     // Literals
-    #[regex(r#""([^"\\]|\\.)*""#, string_literal)]
+    // The closing quote is optional so that unterminated strings are
+    // matched here and reported, instead of being lexed as other tokens.
+    #[regex(r#""([^"\\]|\\.)*["\\]?"#, string_literal)]
     StringLiteral(String),
     
-    #[regex(r"-?[0-9]+(\.[0-9]+)?", |lex| lex.slice().parse::<f64>().ok())]
+    #[regex(r"[0-9]+(\.[0-9]+)?", |lex| lex.slice().parse::<f64>().ok())]
     NumberLiteral(f64),
     
     // Operators
@@ -85,6 +94,15 @@ pub enum Token {
     #[token("/", priority = 1)]
     Divide,
     
+    #[token("%")]
+    Percent,
+    
+    #[token("**")]
+    Power,
+    
+    #[token("~/")]
+    IntegerDivide,
+    
     // Logical operators
     #[token("&&")]
     And,
@@ -136,41 +154,65 @@ pub enum Token {
 
     #[token(".")]
     Dot,
+
+    #[token("..")]
+    DotDot,
+
+    #[token("..=")]
+    DotDotEqual,
 }
 
 #[derive(Debug, Clone, PartialEq, Default)]
-pub struct TokenError;
+pub enum TokenError {
+    #[default]
+    InvalidCharacter,
+    UnterminatedString,
+    UnterminatedComment,
+    /// `offset` is the byte offset of the backslash from the start of the token.
+    InvalidEscape { sequence: char, offset: usize },
+}
 
-fn string_literal(lex: &mut Lexer<Token>) -> Option<String> {
+fn string_literal(lex: &mut Lexer<Token>) -> Result<String, TokenError> {
     let slice = lex.slice();
     
-    let content = &slice[1..slice.len() - 1];
-    
     let mut processed = String::new();
-    let mut chars = content.chars();
-    
-    while let Some(c) = chars.next() {
-        if c == '\\' {
-            if let Some(next) = chars.next() {
-                match next {
-                    'n' => processed.push('\n'),
-                    't' => processed.push('\t'),
-                    'r' => processed.push('\r'),
-                    '\\' => processed.push('\\'),
-                    '"' => processed.push('"'),
-                    _ => {
-                        // Invalid escape sequence
-                        processed.push('\\');
-                        processed.push(next);
-                    }
+    let mut chars = slice.char_indices().skip(1);
+    
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            '"' => return Ok(processed),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => processed.push('\n'),
+                Some((_, 't')) => processed.push('\t'),
+                Some((_, 'r')) => processed.push('\r'),
+                Some((_, '\\')) => processed.push('\\'),
+                Some((_, '"')) => processed.push('"'),
+                Some((_, sequence)) => {
+                    return Err(TokenError::InvalidEscape { sequence, offset });
                 }
-            }
-        } else {
-            processed.push(c);
+                None => break,
+            },
+            _ => processed.push(c),
         }
     }
     
-    Some(processed)
+    Err(TokenError::UnterminatedString)
+}
+
+fn block_comment(lex: &mut Lexer<Token>) -> Result<String, TokenError> {
+    let remainder = lex.remainder();
+    
+    match remainder.find("*/") {
+        Some(end) => {
+            let text = remainder[..end].to_owned();
+            lex.bump(end + 2);
+            Ok(text)
+        }
+        None => {
+            lex.bump(remainder.len());
+            Err(TokenError::UnterminatedComment)
+        }
+    }
 }
 
 pub struct TokenWithSpan {
@@ -184,18 +226,112 @@ impl TokenWithSpan {
     }
 }
 
-pub fn tokenize(source: &str) -> Vec<TokenWithSpan> {
+/// Tokenizes `source`, failing on the first invalid token.
+pub fn tokenize(source: &str) -> Result<Vec<TokenWithSpan>, ParseError> {
+    let (tokens, mut errors) = tokenize_with_errors(source);
+    
+    if errors.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+/// Tokenizes all of `source`, collecting an error for every invalid token
+/// instead of stopping at the first one.
+pub fn tokenize_with_errors(source: &str) -> (Vec<TokenWithSpan>, Vec<ParseError>) {
+    let (tokens, _, errors) = lex(source);
+    (tokens, errors)
+}
+
+/// Like `tokenize`, but also returns the comments of `source`, in order,
+/// for parsing in lossless mode.
+pub fn tokenize_with_comments(source: &str) -> Result<(Vec<TokenWithSpan>, Vec<Comment>), ParseError> {
+    let (tokens, comments, mut errors) = lex(source);
+    
+    if errors.is_empty() {
+        Ok((tokens, comments))
+    } else {
+        Err(errors.remove(0))
+    }
+}
+
+fn lex(source: &str) -> (Vec<TokenWithSpan>, Vec<Comment>, Vec<ParseError>) {
     let mut lexer = Token::lexer(source);
     let mut tokens = Vec::new();
+    let mut comments = Vec::new();
+    let mut errors = Vec::new();
     
     while let Some(token) = lexer.next() {
-        if let Ok(token) = token {
-            tokens.push(TokenWithSpan {
-                token: token.clone(),
-                span: lexer.span(),
-            });
+        let span = lexer.span();
+        match token {
+            Ok(Token::LineComment(text)) => comments.push(Comment::Line {
+                text,
+                location: SourceLocation::from_spans(source, span.start, span.end),
+            }),
+            Ok(Token::BlockComment(text)) => comments.push(Comment::Block {
+                text,
+                location: SourceLocation::from_spans(source, span.start, span.end),
+            }),
+            Ok(token) => tokens.push(TokenWithSpan { token, span }),
+            Err(error) => errors.push(token_error(source, span, error)),
         }
     }
     
-    tokens
+    (tokens, comments, errors)
+}
+
+fn token_error(source: &str, span: Span, error: TokenError) -> ParseError {
+    match error {
+        TokenError::InvalidCharacter => {
+            let character = source[span.start..].chars().next().unwrap_or_default();
+            ParseError::InvalidToken(
+                format!("Invalid character '{}'", character),
+                SourceLocation::from_spans(source, span.start, span.end),
+            )
+        }
+        TokenError::UnterminatedString => ParseError::InvalidToken(
+            "Unterminated string literal".to_string(),
+            SourceLocation::from_spans(source, span.start, span.end),
+        ),
+        TokenError::UnterminatedComment => ParseError::InvalidToken(
+            "Unterminated block comment".to_string(),
+            SourceLocation::from_spans(source, span.start, span.end),
+        ),
+        TokenError::InvalidEscape { sequence, offset } => {
+            let start = span.start + offset;
+            ParseError::InvalidToken(
+                format!("Invalid escape sequence '\\{}'", sequence),
+                SourceLocation::from_spans(source, start, start + 1 + sequence.len_utf8()),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error_messages(source: &str) -> Vec<(String, usize)> {
+        tokenize_with_errors(source)
+            .1
+            .iter()
+            .map(|error| (error.message(), error.location().start_column))
+            .collect()
+    }
+
+    #[test]
+    fn reports_every_invalid_token_with_its_column() {
+        assert_eq!(
+            error_messages("vl a = 1 @ 2; vl b = \"x\\qy\";"),
+            [
+                ("Invalid character '@'".to_string(), 10),
+                ("Invalid escape sequence '\\q'".to_string(), 24),
+            ]
+        );
+        assert_eq!(
+            error_messages("vl s = \"open"),
+            [("Unterminated string literal".to_string(), 8)]
+        );
+    }
 }
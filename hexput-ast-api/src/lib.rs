@@ -1,25 +1,85 @@
 pub mod ast_structs;
+pub mod diagnostics;
 pub mod lexer;
 pub mod parser;
 pub mod optimizer;
+pub mod visitor;
+pub mod printer;
+pub mod analysis;
 pub mod feature_flags;
-pub mod parallel;
 
 use serde_json::{to_string_pretty, to_string, Value};
 use feature_flags::FeatureFlags;
+use analysis::HostEnvironment;
+use diagnostics::Diagnostic;
 use parser::ParseError;
 
 pub fn process_code(code: &str, feature_flags: FeatureFlags) -> Result<ast_structs::Program, ParseError> {
-    let runtime = parallel::create_runtime();
+    let ast = parse_code(code, feature_flags)?;
     
-    let tokens = lexer::tokenize(code);
+    let optimized_ast = optimizer::optimize_ast(ast);
+    
+    Ok(optimized_ast)
+}
+
+/// Parses `code` without optimizing it, so the AST matches the source
+/// statement for statement.
+pub fn parse_code(code: &str, feature_flags: FeatureFlags) -> Result<ast_structs::Program, ParseError> {
+    let tokens = lexer::tokenize(code)?;
     
     let mut parser = parser::Parser::new(&tokens, feature_flags, code);
-    let ast = parser.parse_program()?;
+    parser.parse_program()
+}
+
+/// Like `process_code`, but keeps the comments of `code`, attached to the
+/// statements they precede.
+pub fn process_code_with_comments(code: &str, feature_flags: FeatureFlags) -> Result<ast_structs::Program, ParseError> {
+    let ast = parse_code_with_comments(code, feature_flags)?;
     
-    let optimized_ast = optimizer::optimize_ast(ast, &runtime);
+    Ok(optimizer::optimize_ast(ast))
+}
+
+/// Like `parse_code`, but keeps the comments of `code`, so that printing the
+/// AST gives back the whole source.
+pub fn parse_code_with_comments(code: &str, feature_flags: FeatureFlags) -> Result<ast_structs::Program, ParseError> {
+    let (tokens, comments) = lexer::tokenize_with_comments(code)?;
     
-    Ok(optimized_ast)
+    let mut parser = parser::Parser::new(&tokens, feature_flags, code).with_comments(comments);
+    parser.parse_program()
+}
+
+/// Like `process_code`, but keeps parsing after errors. Returns the
+/// statements that parsed successfully and a diagnostic per error.
+pub fn process_code_with_diagnostics(code: &str, feature_flags: FeatureFlags) -> (ast_structs::Program, Vec<Diagnostic>) {
+    let (ast, diagnostics) = parse_code_with_diagnostics(code, feature_flags);
+    
+    let optimized_ast = optimizer::optimize_ast(ast);
+    
+    (optimized_ast, diagnostics)
+}
+
+/// Parses `code` and runs the checks of `analysis` over the statements that
+/// parsed. Returns the parse and analysis diagnostics together.
+pub fn lint_code(code: &str, feature_flags: FeatureFlags, host: &HostEnvironment) -> Vec<Diagnostic> {
+    let (ast, mut diagnostics) = parse_code_with_diagnostics(code, feature_flags);
+    
+    diagnostics.extend(analysis::analyze(&ast, host));
+    diagnostics.sort_by_key(|d| (d.location.start_line, d.location.start_column));
+    
+    diagnostics
+}
+
+fn parse_code_with_diagnostics(code: &str, feature_flags: FeatureFlags) -> (ast_structs::Program, Vec<Diagnostic>) {
+    let (tokens, token_errors) = lexer::tokenize_with_errors(code);
+    
+    let mut parser = parser::Parser::new(&tokens, feature_flags, code);
+    let (ast, parse_diagnostics) = parser.parse_program_with_diagnostics();
+    
+    let mut diagnostics: Vec<Diagnostic> = token_errors.iter().map(Diagnostic::from).collect();
+    diagnostics.extend(parse_diagnostics);
+    diagnostics.sort_by_key(|d| (d.location.start_line, d.location.start_column));
+    
+    (ast, diagnostics)
 }
 
 pub fn filter_locations(value: Value) -> Value {
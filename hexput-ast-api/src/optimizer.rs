@@ -1,316 +1,566 @@
-use crate::ast_structs::{Block, Expression, Program, Property, Statement};
-use crate::parallel;
-use tokio::runtime::Runtime;
+use crate::ast_structs::{Block, Comment, Expression, Operator, Program, SourceLocation, Statement, UnaryOperator};
+use crate::visitor::{walk_expression_mut, walk_statement, Visitor, VisitorMut};
 
-const PARALLELISM_THRESHOLD: usize = 2; 
+/// Statement lists at least this long are visited in parallel when the
+/// `parallel` feature is enabled.
+#[cfg(feature = "parallel")]
+const PARALLELISM_THRESHOLD: usize = 64;
 
-pub fn optimize_ast(program: Program, runtime: &Runtime) -> Program {
-    
-    let optimized_statements = if program.statements.len() > PARALLELISM_THRESHOLD {
-        parallel::process_items_sync(runtime, program.statements, |stmt, rt| {
-            optimize_statement(stmt, rt)
-        })
-            .into_iter()
-            .filter_map(|s| s)
-            .collect()
-    } else {
-        optimize_statements(program.statements, runtime)
-    };
-    
-    
-    let optimized = Program::new(optimized_statements, program.location);
-  
-    optimized
+/// A transformation of a whole program, run as one step of an `Optimizer`.
+pub trait Pass: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn run(&self, program: Program) -> Program;
 }
 
-fn optimize_statements(statements: Vec<Statement>, runtime: &Runtime) -> Vec<Statement> {
-    let mut optimized = Vec::new();
-    
-    for statement in statements {
-        match optimize_statement(statement, runtime) {
-            Some(stmt) => optimized.push(stmt),
-            None => {} 
+/// Runs a sequence of passes over a program, in order.
+pub struct Optimizer {
+    passes: Vec<Box<dyn Pass>>,
+}
+
+impl Optimizer {
+    /// An optimizer running the built-in passes.
+    pub fn new() -> Self {
+        Self::empty()
+            .with_pass(ConstantFolding)
+            .with_pass(DeadBranchElimination)
+            .with_pass(BlockFlattening)
+            .with_pass(UnreachableCodeElimination)
+    }
+
+    /// An optimizer without any passes.
+    pub fn empty() -> Self {
+        Self { passes: Vec::new() }
+    }
+
+    /// Appends a pass to the pipeline.
+    pub fn with_pass(mut self, pass: impl Pass + 'static) -> Self {
+        self.passes.push(Box::new(pass));
+        self
+    }
+
+    pub fn passes(&self) -> impl Iterator<Item = &dyn Pass> {
+        self.passes.iter().map(|pass| pass.as_ref())
+    }
+
+    pub fn run(&self, program: Program) -> Program {
+        self.passes.iter().fold(program, |program, pass| pass.run(program))
+    }
+}
+
+impl Default for Optimizer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Optimizes a program with the built-in passes.
+pub fn optimize_ast(program: Program) -> Program {
+    Optimizer::new().run(program)
+}
+
+/// Evaluates operators whose operands are all literals.
+#[derive(Clone, Copy)]
+pub struct ConstantFolding;
+
+impl Pass for ConstantFolding {
+    fn name(&self) -> &'static str {
+        "constant-folding"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        run_pass(*self, program)
+    }
+}
+
+impl VisitorMut for ConstantFolding {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        visit_statements(self, &mut program.statements);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        visit_statements(self, &mut block.statements);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+
+        let folded = match &*expression {
+            Expression::BinaryExpression { left, operator, right, location } => fold_binary(left, operator, right, location),
+            Expression::UnaryExpression { operator, operand, location } => fold_unary(operator, operand, location),
+            _ => None,
+        };
+        
+        if let Some(folded) = folded {
+            *expression = folded;
         }
     }
-    
-    optimized
 }
 
-fn optimize_statement(statement: Statement, runtime: &Runtime) -> Option<Statement> {
+impl RewriteStatements for ConstantFolding {}
+
+/// Keeps only the branch a constant `if` condition selects, drops
+/// `loop while` statements whose condition is constantly false, and removes
+/// empty blocks. An `if` without anything to run is reduced to its
+/// condition.
+#[derive(Clone, Copy)]
+pub struct DeadBranchElimination;
+
+impl Pass for DeadBranchElimination {
+    fn name(&self) -> &'static str {
+        "dead-branch-elimination"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        run_pass(*self, program)
+    }
+}
+
+impl VisitorMut for DeadBranchElimination {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        visit_statements(self, &mut program.statements);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        visit_statements(self, &mut block.statements);
+    }
+}
+
+impl RewriteStatements for DeadBranchElimination {
+    fn rewrite_statements(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        statements.into_iter().filter_map(eliminate_dead_branch).collect()
+    }
+}
+
+fn eliminate_dead_branch(statement: Statement) -> Option<Statement> {
     match statement {
-        Statement::Block { block, location } => {
-            
-            let optimized_block = optimize_block(block, runtime);
-            
-            
-            if optimized_block.statements.is_empty() {
-                return None;
-            }
-            
-            
-            
-            if optimized_block.statements.len() == 1 {
-                match &optimized_block.statements[0] {
-                    Statement::Block { .. } => {},
-                    _ => return Some(optimized_block.statements.into_iter().next().unwrap())
-                }
+        Statement::Block { block, .. } if block.statements.is_empty() => None,
+        Statement::IfStatement { condition, body, else_body, comments, location } => {
+            if let Some(is_truthy) = literal_truthiness(&condition) {
+                let branch = if is_truthy { Some(body) } else { else_body };
+                return branch
+                    .filter(|block| !block.statements.is_empty())
+                    .map(|block| Statement::Block { block, comments, location });
             }
             
-            Some(Statement::Block { block: optimized_block, location })
-        },
-        Statement::IfStatement { condition, body, else_body, location } => {
-            
-            let optimized_condition = optimize_expression(condition, runtime);
-            
-            
-            let optimized_body = optimize_block(body, runtime);
-            
-            
-            let optimized_else_body = else_body.map(|body| optimize_block(body, runtime));
-            
-            
-            if optimized_body.statements.is_empty() && 
-               optimized_else_body.as_ref().map_or(true, |b| b.statements.is_empty()) {
-                return None;
+            // Evaluating the condition may still call functions or fail,
+            // for example on an undefined variable.
+            if body.statements.is_empty() && else_body.as_ref().is_none_or(|b| b.statements.is_empty()) {
+                return Some(Statement::ExpressionStatement { expression: condition, comments, location });
             }
             
-            Some(Statement::IfStatement { 
-                condition: optimized_condition, 
-                body: optimized_body,
-                else_body: optimized_else_body,
-                location
-            })
+            Some(Statement::IfStatement { condition, body, else_body, comments, location })
         },
-        Statement::ExpressionStatement { expression, location } => {
-            let optimized_expr = optimize_expression(expression, runtime);
-            
-            Some(Statement::ExpressionStatement { expression: optimized_expr, location })
-        },
-        Statement::CallbackDeclaration { name, params, body, location } => {
-            
-            let optimized_body = optimize_block(body, runtime);
-            
-            Some(Statement::CallbackDeclaration {
-                name,
-                params,
-                body: optimized_body,
-                location,
-            })
-        },
-        Statement::ReturnStatement { value, location } => {
-            
-            let optimized_value = optimize_expression(value, runtime);
-            
-            
-            Some(Statement::ReturnStatement { value: optimized_value, location })
-        },
-        Statement::LoopStatement { variable, iterable, body, location } => {
-            
-            let optimized_iterable = optimize_expression(iterable, runtime);
-            
-            
-            let optimized_body = optimize_block(body, runtime);
-            
-            
-            if optimized_body.statements.is_empty() {
-                return None;
+        // Loops are kept even when their body is empty: the iterable may
+        // call functions or not be iterable, and the condition of a
+        // `loop while` is evaluated on every iteration.
+        Statement::WhileLoopStatement { ref condition, .. } if literal_truthiness(condition) == Some(false) => None,
+        _ => Some(statement),
+    }
+}
+
+/// Merges nested blocks into their enclosing block and unwraps blocks
+/// holding a single statement, unless they declare names of their own.
+#[derive(Clone, Copy)]
+pub struct BlockFlattening;
+
+impl Pass for BlockFlattening {
+    fn name(&self) -> &'static str {
+        "block-flattening"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        run_pass(*self, program)
+    }
+}
+
+impl VisitorMut for BlockFlattening {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        // A block stops at an `end` or `continue` outside of a loop, but the
+        // top level ignores them and runs the statements that follow, so
+        // such blocks are kept there.
+        visit_each(self, &mut program.statements);
+        let statements = std::mem::take(&mut program.statements);
+        program.statements = flatten_blocks(statements, |block| is_mergeable(block) && !has_loop_control(block));
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        visit_statements(self, &mut block.statements);
+    }
+}
+
+impl RewriteStatements for BlockFlattening {
+    fn rewrite_statements(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        flatten_blocks(statements, is_mergeable)
+    }
+}
+
+/// Merges the blocks `mergeable` accepts into the list of statements.
+fn flatten_blocks(statements: Vec<Statement>, mergeable: impl Fn(&Block) -> bool) -> Vec<Statement> {
+    let mut flattened = Vec::with_capacity(statements.len());
+    for statement in statements.into_iter().map(|stmt| unwrap_single_statement(stmt, &mergeable)) {
+        match statement {
+            Statement::Block { block, comments, .. } if mergeable(&block) => {
+                let mut inner = block.statements.into_iter();
+                flattened.extend(inner.next().map(|stmt| prepend_comments(comments, stmt)));
+                flattened.extend(inner);
+            },
+            _ => flattened.push(statement),
+        }
+    }
+    
+    flattened
+}
+
+/// Replaces a block holding a single statement, other than a block, with
+/// that statement.
+fn unwrap_single_statement(statement: Statement, mergeable: impl Fn(&Block) -> bool) -> Statement {
+    match statement {
+        Statement::Block { block, comments, location } => {
+            if block.statements.len() == 1 && mergeable(&block) && !matches!(block.statements[0], Statement::Block { .. }) {
+                let mut statements = block.statements;
+                return prepend_comments(comments, statements.remove(0));
             }
             
-            Some(Statement::LoopStatement {
-                variable,
-                iterable: optimized_iterable,
-                body: optimized_body,
-                location,
-            })
+            Statement::Block { block, comments, location }
         },
+        _ => statement,
+    }
+}
+
+/// Drops the statements following a `res`, `end` or `continue`, which can
+/// never run. At the top level, where `end` and `continue` are ignored,
+/// only the statements following a `res` are dropped.
+#[derive(Clone, Copy)]
+pub struct UnreachableCodeElimination;
+
+impl Pass for UnreachableCodeElimination {
+    fn name(&self) -> &'static str {
+        "unreachable-code-elimination"
+    }
+
+    fn run(&self, program: Program) -> Program {
+        run_pass(*self, program)
+    }
+}
+
+impl VisitorMut for UnreachableCodeElimination {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        visit_each(self, &mut program.statements);
+        truncate_after(&mut program.statements, |stmt| matches!(stmt, Statement::ReturnStatement { .. }));
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        visit_statements(self, &mut block.statements);
+    }
+}
+
+impl RewriteStatements for UnreachableCodeElimination {
+    fn rewrite_statements(&self, mut statements: Vec<Statement>) -> Vec<Statement> {
+        truncate_after(&mut statements, |stmt| matches!(
+            stmt,
+            Statement::ReturnStatement { .. } | Statement::EndStatement { .. } | Statement::ContinueStatement { .. }
+        ));
         
-        Statement::EndStatement { location } => Some(Statement::EndStatement { location }),
-        Statement::ContinueStatement { location } => Some(Statement::ContinueStatement { location }),
-        
-        
-        Statement::VariableDeclaration { name, value, location } => {
-            let optimized_value = optimize_expression(value, runtime);
-            Some(Statement::VariableDeclaration { name, value: optimized_value, location })
-        }
+        statements
+    }
+}
+
+/// Drops the statements following the first one `is_exit` accepts.
+fn truncate_after(statements: &mut Vec<Statement>, is_exit: impl Fn(&Statement) -> bool) {
+    if let Some(index) = statements.iter().position(is_exit) {
+        statements.truncate(index + 1);
     }
 }
 
-fn optimize_block(block: Block, runtime: &Runtime) -> Block {
+/// Whether the statements of the block can be moved into the enclosing
+/// block without changing scoping or losing comments.
+fn is_mergeable(block: &Block) -> bool {
+    !declares_names(block) && block.trailing_comments.is_empty()
+}
+
+/// Whether the block contains an `end` or `continue`, at any depth.
+fn has_loop_control(block: &Block) -> bool {
+    struct LoopControlFinder(bool);
     
-    let statements = if block.statements.len() > PARALLELISM_THRESHOLD {
-        parallel::process_items_sync(runtime, block.statements, |stmt, rt| optimize_statement(stmt, rt))
-            .into_iter()
-            .filter_map(|s| s)
-            .collect()
-    } else {
-        optimize_statements(block.statements, runtime)
-    };
+    impl<'ast> Visitor<'ast> for LoopControlFinder {
+        fn visit_statement(&mut self, statement: &'ast Statement) {
+            self.0 |= matches!(statement, Statement::EndStatement { .. } | Statement::ContinueStatement { .. });
+            walk_statement(self, statement);
+        }
+    }
     
+    let mut finder = LoopControlFinder(false);
+    finder.visit_block(block);
+    finder.0
+}
+
+/// Puts `comments` before the comments already attached to `statement`.
+fn prepend_comments(mut comments: Vec<Comment>, mut statement: Statement) -> Statement {
+    comments.append(statement.comments_mut());
+    *statement.comments_mut() = comments;
+    statement
+}
+
+/// Whether the block declares variables or callbacks of its own. Such blocks
+/// are scopes and cannot be merged into the enclosing block.
+fn declares_names(block: &Block) -> bool {
+    block.statements.iter().any(|stmt| matches!(
+        stmt,
+        Statement::VariableDeclaration { .. } | Statement::CallbackDeclaration { .. }
+    ))
+}
+
+/// The statement list step of the built-in passes. Each pass visits the
+/// statements of a block or of the program with `visit_statements`, so that
+/// the list is rewritten after every statement in it, bottom-up.
+trait RewriteStatements: VisitorMut + Clone + Send + Sync {
+    fn rewrite_statements(&self, statements: Vec<Statement>) -> Vec<Statement> {
+        statements
+    }
+}
+
+fn run_pass<P: RewriteStatements>(mut pass: P, mut program: Program) -> Program {
+    pass.visit_program_mut(&mut program);
+    program
+}
+
+fn visit_statements<P: RewriteStatements>(pass: &mut P, statements: &mut Vec<Statement>) {
+    visit_each(pass, statements);
+    let visited = std::mem::take(statements);
+    *statements = pass.rewrite_statements(visited);
+}
+
+/// Visits the statements in order, across threads for long lists when the
+/// `parallel` feature is enabled.
+#[cfg(feature = "parallel")]
+fn visit_each<P: RewriteStatements>(pass: &mut P, statements: &mut [Statement]) {
+    use rayon::prelude::*;
     
-    let mut flattened = Vec::new();
-    for stmt in statements {
-        match stmt {
-            
-            Statement::Block { block: inner_block, .. } => {
-                flattened.extend(inner_block.statements);
-            },
-            _ => flattened.push(stmt)
+    if statements.len() >= PARALLELISM_THRESHOLD {
+        let pass = &*pass;
+        statements.par_iter_mut().for_each(|stmt| pass.clone().visit_statement_mut(stmt));
+    } else {
+        for stmt in statements {
+            pass.visit_statement_mut(stmt);
         }
     }
-    
-    Block::new(flattened, block.location)
 }
 
+#[cfg(not(feature = "parallel"))]
+fn visit_each<P: RewriteStatements>(pass: &mut P, statements: &mut [Statement]) {
+    for stmt in statements {
+        pass.visit_statement_mut(stmt);
+    }
+}
 
-fn optimize_expression(expr: Expression, runtime: &Runtime) -> Expression {
+/// Truthiness of a literal, following the rules the runtime applies to
+/// conditions. `None` when the expression is not a literal.
+fn literal_truthiness(expr: &Expression) -> Option<bool> {
     match expr {
-        Expression::BinaryExpression { left, operator, right, location } => {
-            
-            let optimized_left = optimize_expression(*left, runtime);
-            let optimized_right = optimize_expression(*right, runtime);
-            let result = Expression::BinaryExpression {
-                left: Box::new(optimized_left),
-                operator,
-                right: Box::new(optimized_right),
-                location,
-            };
-            result
-        },
-        Expression::AssignmentExpression { target, value, location } => {
-            let optimized_value = Box::new(optimize_expression(*value, runtime));
-            let result = Expression::AssignmentExpression { target, value: optimized_value, location };
-            result
-        },
-        Expression::MemberAssignmentExpression { object, property, property_expr, computed, value, location } => {
-            
-            let optimized_object = Box::new(optimize_expression(*object, runtime));
-            
-            
-            let optimized_prop_expr = match property_expr {
-                Some(expr) => Some(Box::new(optimize_expression(*expr, runtime))),
-                None => None,
-            };
-            
-            let optimized_value = Box::new(optimize_expression(*value, runtime));
-            let result = Expression::MemberAssignmentExpression {
-                object: optimized_object,
-                property,
-                property_expr: optimized_prop_expr,
-                computed,
-                value: optimized_value,
-                location,
-            };
-            result
+        Expression::BooleanLiteral { value, .. } => Some(*value),
+        Expression::NumberLiteral { value, .. } => Some(*value != 0.0),
+        Expression::StringLiteral { value, .. } => Some(!value.is_empty()),
+        Expression::NullLiteral { .. } => Some(false),
+        _ => None,
+    }
+}
+
+fn number_literal(value: f64, location: &SourceLocation) -> Option<Expression> {
+    // The runtime reports non-finite results as errors, which must still
+    // happen at execution time.
+    value.is_finite().then_some(Expression::NumberLiteral { value, location: *location })
+}
+
+fn boolean_literal(value: bool, location: &SourceLocation) -> Option<Expression> {
+    Some(Expression::BooleanLiteral { value, location: *location })
+}
+
+/// Evaluates a binary expression over literals the same way the runtime
+/// would. Returns `None` when an operand is not a literal or when the
+/// runtime would raise an error, so the error is still reported.
+fn fold_binary(left: &Expression, operator: &Operator, right: &Expression, location: &SourceLocation) -> Option<Expression> {
+    match operator {
+        Operator::And => {
+            if !literal_truthiness(left)? {
+                return boolean_literal(false, location);
+            }
+            boolean_literal(literal_truthiness(right)?, location)
         },
-        Expression::CallExpression { callee, arguments, location } => {
-            
-            let optimized_args = if arguments.len() > PARALLELISM_THRESHOLD {
-                parallel::process_items_sync(runtime, arguments, |arg, rt| optimize_expression(arg, rt))
-            } else {
-                arguments.into_iter().map(|arg| optimize_expression(arg, runtime)).collect()
-            };
-            let result = Expression::CallExpression { callee, arguments: optimized_args, location };
-            result
+        Operator::Or => {
+            if literal_truthiness(left)? {
+                return boolean_literal(true, location);
+            }
+            boolean_literal(literal_truthiness(right)?, location)
         },
-        Expression::ArrayExpression { elements, location } => {
-            
-            let optimized_elements = if elements.len() > PARALLELISM_THRESHOLD {
-                parallel::process_items_sync(runtime, elements, |elem, rt| optimize_expression(elem, rt))
-            } else {
-                elements.into_iter().map(|elem| optimize_expression(elem, runtime)).collect()
+        Operator::Equal | Operator::NotEqual => {
+            let equal = match (left, right) {
+                (Expression::NullLiteral { .. }, Expression::NullLiteral { .. }) => true,
+                (Expression::BooleanLiteral { value: l, .. }, Expression::BooleanLiteral { value: r, .. }) => l == r,
+                (Expression::NumberLiteral { value: l, .. }, Expression::NumberLiteral { value: r, .. }) => (l - r).abs() < f64::EPSILON,
+                (Expression::StringLiteral { value: l, .. }, Expression::StringLiteral { value: r, .. }) => l == r,
+                // Literals of different types are never equal.
+                _ if literal_truthiness(left).is_some() && literal_truthiness(right).is_some() => false,
+                _ => return None,
             };
-            let result = Expression::ArrayExpression { elements: optimized_elements, location };
-            result
+            boolean_literal(equal == (*operator == Operator::Equal), location)
         },
-        Expression::ObjectExpression { properties, location } => {
-            
-            let optimized_properties = if properties.len() > PARALLELISM_THRESHOLD {
-                parallel::process_items_sync(
-                    runtime,
-                    properties,
-                    |prop, rt| Property::new(prop.key, optimize_expression(prop.value, rt), prop.location)
-                )
-            } else {
-                properties.into_iter()
-                    .map(|prop| Property::new(prop.key, optimize_expression(prop.value, runtime), prop.location))
-                    .collect()
-            };
-            let result = Expression::ObjectExpression { properties: optimized_properties, location };
-            result
+        Operator::Plus => match (left, right) {
+            (Expression::NumberLiteral { value: l, .. }, Expression::NumberLiteral { value: r, .. }) => number_literal(l + r, location),
+            (Expression::StringLiteral { value: l, .. }, Expression::StringLiteral { value: r, .. }) => {
+                Some(Expression::StringLiteral { value: format!("{}{}", l, r), location: *location })
+            },
+            (Expression::StringLiteral { value: l, .. }, Expression::NumberLiteral { value: r, .. }) => {
+                Some(Expression::StringLiteral { value: format!("{}{}", l, r), location: *location })
+            },
+            (Expression::NumberLiteral { value: l, .. }, Expression::StringLiteral { value: r, .. }) => {
+                Some(Expression::StringLiteral { value: format!("{}{}", l, r), location: *location })
+            },
+            _ => None,
         },
-        Expression::MemberExpression { object, property, property_expr, computed, location } => {
-            
-            let optimized_object = Box::new(optimize_expression(*object, runtime));
-            
-            
-            let optimized_prop_expr = match property_expr {
-                Some(expr) => Some(Box::new(optimize_expression(*expr, runtime))),
-                None => None,
+        Operator::Less | Operator::Greater | Operator::LessEqual | Operator::GreaterEqual => {
+            let ordering = match (left, right) {
+                (Expression::NumberLiteral { value: l, .. }, Expression::NumberLiteral { value: r, .. }) => l.partial_cmp(r)?,
+                (Expression::StringLiteral { value: l, .. }, Expression::StringLiteral { value: r, .. }) => l.cmp(r),
+                _ => return None,
             };
-            let result = Expression::MemberExpression {
-                object: optimized_object,
-                property,
-                property_expr: optimized_prop_expr,
-                computed,
-                location,
+            let result = match operator {
+                Operator::Less => ordering.is_lt(),
+                Operator::Greater => ordering.is_gt(),
+                Operator::LessEqual => ordering.is_le(),
+                _ => ordering.is_ge(),
             };
-            result
+            boolean_literal(result, location)
         },
-        Expression::KeysOfExpression { object, location } => {
-            let optimized_object = Box::new(optimize_expression(*object, runtime));
-            let result = Expression::KeysOfExpression { object: optimized_object, location };
-            result
-        },
-        Expression::MemberCallExpression { object, property, property_expr, computed, arguments, location } => {
-            
-            let optimized_object = Box::new(optimize_expression(*object, runtime));
-            
-            
-            let optimized_prop_expr = match property_expr {
-                Some(expr) => Some(Box::new(optimize_expression(*expr, runtime))),
-                None => None,
+        Operator::Minus | Operator::Multiply | Operator::Divide | Operator::IntegerDivide | Operator::Modulo | Operator::Power => {
+            let (l, r) = match (left, right) {
+                (Expression::NumberLiteral { value: l, .. }, Expression::NumberLiteral { value: r, .. }) => (*l, *r),
+                _ => return None,
             };
-            
-            
-            let optimized_args = if arguments.len() > PARALLELISM_THRESHOLD {
-                parallel::process_items_sync(runtime, arguments, |arg, rt| optimize_expression(arg, rt))
-            } else {
-                arguments.into_iter().map(|arg| optimize_expression(arg, runtime)).collect()
+            let result = match operator {
+                Operator::Minus => l - r,
+                Operator::Multiply => l * r,
+                Operator::Divide if r != 0.0 => l / r,
+                Operator::IntegerDivide if r != 0.0 => (l / r).floor(),
+                Operator::Modulo if r != 0.0 => match l % r {
+                    m if m != 0.0 && (m < 0.0) != (r < 0.0) => m + r,
+                    m => m,
+                },
+                Operator::Power => l.powf(r),
+                _ => return None,
             };
-            
-            Expression::MemberCallExpression {
-                object: optimized_object,
-                property,
-                property_expr: optimized_prop_expr,
-                computed,
-                arguments: optimized_args,
-                location,
-            }
-        },
-        Expression::InlineCallbackExpression { name, params, body, location } => {
-            let optimized_body = optimize_block(body, runtime);
-            
-            Expression::InlineCallbackExpression {
-                name,
-                params,
-                body: optimized_body,
-                location,
-            }
-        },
-        Expression::UnaryExpression { operator, operand, location } => {
-            let optimized_operand = Box::new(optimize_expression(*operand, runtime));
-            Expression::UnaryExpression { 
-                operator,
-                operand: optimized_operand,
-                location,
-            }
-        },
-        Expression::StringLiteral { .. } |
-        Expression::NumberLiteral { .. } |
-        Expression::Identifier { .. } |
-        Expression::BooleanLiteral { .. } |
-        Expression::NullLiteral { .. } => {
-            expr
+            number_literal(result, location)
         },
     }
 }
+
+/// Evaluates a unary expression over a literal the same way the runtime would.
+fn fold_unary(operator: &UnaryOperator, operand: &Expression, location: &SourceLocation) -> Option<Expression> {
+    match (operator, operand) {
+        (UnaryOperator::Not, _) => boolean_literal(!literal_truthiness(operand)?, location),
+        (UnaryOperator::Negate, Expression::NumberLiteral { value, .. }) => number_literal(-value, location),
+        (UnaryOperator::Plus, Expression::NumberLiteral { value, .. }) => number_literal(*value, location),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature_flags::FeatureFlags;
+
+    fn optimize(code: &str) -> Vec<Statement> {
+        crate::process_code(code, FeatureFlags::default()).unwrap().statements
+    }
+
+    fn returned(code: &str) -> Expression {
+        match optimize(code).pop() {
+            Some(Statement::ReturnStatement { value, .. }) => value,
+            other => panic!("expected a return statement, got {:?}", other),
+        }
+    }
+
+    fn number(expression: &Expression) -> f64 {
+        match expression {
+            Expression::NumberLiteral { value, .. } => *value,
+            other => panic!("expected a number literal, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn folds_operators_over_literals() {
+        assert_eq!(number(&returned("res 1 + 2 * 3;")), 7.0);
+        assert_eq!(number(&returned("res -(2 ** 3);")), -8.0);
+        assert!(matches!(returned("res 2 ** 3 > 7;"), Expression::BooleanLiteral { value: true, .. }));
+        assert!(matches!(returned("res 1 / 0;"), Expression::BinaryExpression { .. }));
+        assert!(matches!(returned("res a + 1;"), Expression::BinaryExpression { .. }));
+    }
+
+    #[test]
+    fn drops_statements_after_res() {
+        assert_eq!(optimize("res 1; run();").len(), 1);
+        match optimize("if c { res 1; run(); }").as_slice() {
+            [Statement::IfStatement { body, .. }] => assert_eq!(body.statements.len(), 1),
+            other => panic!("expected an if statement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn keeps_side_effects_of_empty_branches_and_loops() {
+        assert!(optimize("if 1 {} else {} {}").is_empty());
+
+        match optimize("if x {}").as_slice() {
+            [Statement::ExpressionStatement { expression: Expression::Identifier { name, .. }, .. }] => assert_eq!(name, "x"),
+            other => panic!("expected the condition to be kept, got {:?}", other),
+        }
+
+        match optimize("if check() {} else {}").as_slice() {
+            [Statement::ExpressionStatement { expression: Expression::CallExpression { callee, .. }, .. }] => assert_eq!(callee, "check"),
+            other => panic!("expected the condition to be kept, got {:?}", other),
+        }
+
+        assert!(matches!(optimize("loop i in items {}").as_slice(), [Statement::LoopStatement { .. }]));
+        assert!(matches!(optimize("loop i in 5 {}").as_slice(), [Statement::LoopStatement { .. }]));
+        assert!(matches!(optimize("loop while next() {}").as_slice(), [Statement::WhileLoopStatement { .. }]));
+    }
+
+    #[test]
+    fn keeps_only_the_selected_branch_of_constant_conditions() {
+        match optimize("if 1 < 2 { run(); } else { skip(); }").as_slice() {
+            [Statement::ExpressionStatement { expression: Expression::CallExpression { callee, .. }, .. }] => assert_eq!(callee, "run"),
+            other => panic!("expected the first branch, got {:?}", other),
+        }
+        assert!(optimize("if false { skip(); } loop while false { skip(); }").is_empty());
+    }
+
+    #[test]
+    fn keeps_statements_after_top_level_loop_control() {
+        assert_eq!(optimize("end; res 5;").len(), 2);
+        match optimize("loop i in items { end; run(); }").as_slice() {
+            [Statement::LoopStatement { body, .. }] => assert_eq!(body.statements.len(), 1),
+            other => panic!("expected a loop, got {:?}", other),
+        }
+        assert!(matches!(optimize("{ if c { end; } run(); } res 5;").as_slice(), [Statement::Block { .. }, _]));
+    }
+
+    #[test]
+    fn folds_modulo_with_the_sign_of_the_divisor() {
+        assert_eq!(number(&returned("res -7 % 2;")), 1.0);
+        assert_eq!(number(&returned("res 7 % -2;")), -1.0);
+        assert_eq!(number(&returned("res -7 ~/ 2;")), -4.0);
+        assert_eq!(number(&returned("res (-7 ~/ 2) * 2 + -7 % 2;")), -7.0);
+    }
+
+    #[test]
+    fn runs_only_the_passes_it_is_given() {
+        let names: Vec<_> = Optimizer::new().passes().map(|pass| pass.name()).collect();
+        assert_eq!(names, ["constant-folding", "dead-branch-elimination", "block-flattening", "unreachable-code-elimination"]);
+
+        let code = "if 1 > 2 { run(); }";
+        let tokens = crate::lexer::tokenize(code).unwrap();
+        let program = crate::parser::Parser::new(&tokens, FeatureFlags::default(), code).parse_program().unwrap();
+        match Optimizer::empty().with_pass(ConstantFolding).run(program).statements.as_slice() {
+            [Statement::IfStatement { condition: Expression::BooleanLiteral { value: false, .. }, .. }] => {}
+            other => panic!("expected only the condition to be folded, got {:?}", other),
+        }
+    }
+}
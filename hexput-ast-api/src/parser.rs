@@ -1,89 +1,22 @@
-use crate::ast_structs::{Block, Expression, Operator, Program, Property, Statement, SourceLocation, UnaryOperator};
+use crate::ast_structs::{Block, Comment, Expression, Operator, Program, Property, Statement, SourceLocation, UnaryOperator};
+use crate::diagnostics::Diagnostic;
 use crate::feature_flags::FeatureFlags;
 use crate::lexer::{Token, TokenWithSpan};
+use std::collections::VecDeque;
 use std::fmt;
 use std::iter::Peekable;
 use std::slice::Iter;
 use rand::distr::Alphanumeric;
 use rand::{rng, Rng};
 
-// Define a macro to extract location from an expression
-macro_rules! get_expr_location {
-    ($expr:expr) => {
-        match &$expr {
-            Expression::StringLiteral { location, .. } |
-            Expression::NumberLiteral { location, .. } |
-            Expression::Identifier { location, .. } |
-            Expression::BinaryExpression { location, .. } |
-            Expression::AssignmentExpression { location, .. } |
-            Expression::MemberAssignmentExpression { location, .. } |
-            Expression::CallExpression { location, .. } |
-            Expression::MemberCallExpression { location, .. } |  
-            Expression::InlineCallbackExpression { location, .. } |
-            Expression::ArrayExpression { location, .. } |
-            Expression::ObjectExpression { location, .. } |
-            Expression::MemberExpression { location, .. } |
-            Expression::KeysOfExpression { location, .. } |
-            Expression::BooleanLiteral { location, .. } |
-            Expression::UnaryExpression { location, .. } |
-            Expression::NullLiteral { location, .. } => location.clone(),
-        }
-    };
-}
-
-// Define a macro to extract start line from an expression
-macro_rules! get_expr_start_line {
-    ($expr:expr) => {
-        match &$expr {
-            Expression::StringLiteral { location, .. } |
-            Expression::NumberLiteral { location, .. } |
-            Expression::Identifier { location, .. } |
-            Expression::BinaryExpression { location, .. } |
-            Expression::AssignmentExpression { location, .. } |
-            Expression::MemberAssignmentExpression { location, .. } |
-            Expression::CallExpression { location, .. } |
-            Expression::MemberCallExpression { location, .. } |  
-            Expression::InlineCallbackExpression { location, .. } |
-            Expression::ArrayExpression { location, .. } |
-            Expression::ObjectExpression { location, .. } |
-            Expression::MemberExpression { location, .. } |
-            Expression::KeysOfExpression { location, .. } |
-            Expression::BooleanLiteral { location, .. } |
-            Expression::UnaryExpression { location, .. } |
-            Expression::NullLiteral { location, .. } => location.start_line,
-        }
-    };
-}
-
-// Define a macro to extract start column from an expression
-macro_rules! get_expr_start_column {
-    ($expr:expr) => {
-        match &$expr {
-            Expression::StringLiteral { location, .. } |
-            Expression::NumberLiteral { location, .. } |
-            Expression::Identifier { location, .. } |
-            Expression::BinaryExpression { location, .. } |
-            Expression::AssignmentExpression { location, .. } |
-            Expression::MemberAssignmentExpression { location, .. } |
-            Expression::CallExpression { location, .. } |
-            Expression::MemberCallExpression { location, .. } |  
-            Expression::InlineCallbackExpression { location, .. } |
-            Expression::ArrayExpression { location, .. } |
-            Expression::ObjectExpression { location, .. } |
-            Expression::MemberExpression { location, .. } |
-            Expression::KeysOfExpression { location, .. } |
-            Expression::BooleanLiteral { location, .. } |
-            Expression::UnaryExpression { location, .. } |
-            Expression::NullLiteral { location, .. } => location.start_column,
-        }
-    };
-}
-
 pub struct Parser<'a> {
     tokens: Peekable<Iter<'a, TokenWithSpan>>,
     current_token: Option<&'a TokenWithSpan>,
     flags: FeatureFlags,
     source_code: &'a str,
+    recover: bool,
+    diagnostics: Vec<Diagnostic>,
+    comments: VecDeque<Comment>,
 }
 
 #[derive(Debug)]
@@ -92,23 +25,46 @@ pub enum ParseError {
     ExpectedToken(String, SourceLocation),
     EndOfInput(SourceLocation),
     FeatureDisabled(String, SourceLocation),
+    InvalidToken(String, SourceLocation),
 }
 
 impl fmt::Display for ParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let loc = self.location();
+        write!(f, "{} at line {}, column {}", self.message(), loc.start_line, loc.start_column)
+    }
+}
+
+impl ParseError {
+    pub fn kind(&self) -> &'static str {
         match self {
-            ParseError::UnexpectedToken(msg, loc) => 
-                write!(f, "Unexpected token: {} at line {}, column {}", 
-                    msg, loc.start_line, loc.start_column),
-            ParseError::ExpectedToken(msg, loc) => 
-                write!(f, "Expected token: {} at line {}, column {}", 
-                    msg, loc.start_line, loc.start_column),
-            ParseError::EndOfInput(loc) => 
-                write!(f, "Unexpected end of input at line {}, column {}", 
-                    loc.start_line, loc.start_column),
-            ParseError::FeatureDisabled(feature, loc) => 
-                write!(f, "Feature disabled: {} is not allowed with current settings at line {}, column {}", 
-                    feature, loc.start_line, loc.start_column),
+            ParseError::UnexpectedToken(..) => "UnexpectedToken",
+            ParseError::ExpectedToken(..) => "ExpectedToken",
+            ParseError::EndOfInput(..) => "EndOfInput",
+            ParseError::FeatureDisabled(..) => "FeatureDisabled",
+            ParseError::InvalidToken(..) => "InvalidToken",
+        }
+    }
+
+    /// The error description without its location.
+    pub fn message(&self) -> String {
+        match self {
+            ParseError::UnexpectedToken(msg, _) => format!("Unexpected token: {}", msg),
+            ParseError::ExpectedToken(msg, _) => format!("Expected token: {}", msg),
+            ParseError::EndOfInput(_) => "Unexpected end of input".to_string(),
+            ParseError::FeatureDisabled(feature, _) => 
+                format!("Feature disabled: {} is not allowed with current settings", feature),
+            ParseError::InvalidToken(msg, _) => msg.clone(),
+        }
+    }
+
+    pub fn location(&self) -> SourceLocation {
+        match self {
+            ParseError::UnexpectedToken(_, loc)
+            | ParseError::ExpectedToken(_, loc)
+            | ParseError::EndOfInput(loc)
+            | ParseError::FeatureDisabled(_, loc)
+            | ParseError::InvalidToken(_, loc) => *loc,
         }
     }
 }
@@ -120,11 +76,38 @@ impl<'a> Parser<'a> {
             current_token: None,
             flags,
             source_code,
+            recover: false,
+            diagnostics: Vec::new(),
+            comments: VecDeque::new(),
         };
         parser.advance();
         parser
     }
 
+    /// Attaches `comments`, as returned by `lexer::tokenize_with_comments`,
+    /// to the statements they precede. Comments inside a statement are
+    /// attached to that statement, and those after the last statement of a
+    /// block or program to the block or program.
+    pub fn with_comments(mut self, comments: Vec<Comment>) -> Self {
+        self.comments = comments.into();
+        self
+    }
+
+    /// Removes the pending comments that start before `line`, `column`.
+    fn take_comments_before(&mut self, line: usize, column: usize) -> Vec<Comment> {
+        let mut taken = Vec::new();
+        
+        while let Some(comment) = self.comments.front() {
+            let start = comment.location();
+            if (start.start_line, start.start_column) >= (line, column) {
+                break;
+            }
+            taken.extend(self.comments.pop_front());
+        }
+        
+        taken
+    }
+
     fn current_location(&self) -> SourceLocation {
         match self.current_token {
             Some(token) => token.get_location(self.source_code),
@@ -158,14 +141,64 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Records `error` and skips to the next statement boundary when parsing
+    /// in recovery mode; otherwise returns the error.
+    fn recover_from(&mut self, error: ParseError, in_block: bool) -> Result<(), ParseError> {
+        if !self.recover {
+            return Err(error);
+        }
+
+        self.diagnostics.push(Diagnostic::from(&error));
+
+        while let Some(token_with_span) = self.current_token {
+            match token_with_span.token {
+                Token::Semicolon => {
+                    self.advance();
+                    break;
+                }
+                // Inside a block the closing brace is left for `parse_block`.
+                Token::CloseBrace => {
+                    if !in_block {
+                        self.advance();
+                    }
+                    break;
+                }
+                _ => self.advance(),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses the whole input, recovering from errors at `;` and `}`.
+    ///
+    /// Returns the statements that could be parsed together with a
+    /// diagnostic for every error found.
+    pub fn parse_program_with_diagnostics(&mut self) -> (Program, Vec<Diagnostic>) {
+        self.recover = true;
+
+        let program = match self.parse_program() {
+            Ok(program) => program,
+            Err(error) => {
+                self.diagnostics.push(Diagnostic::from(&error));
+                let end_pos = self.source_code.len();
+                Program::new(Vec::new(), SourceLocation::from_spans(self.source_code, 0, end_pos))
+            }
+        };
+
+        (program, std::mem::take(&mut self.diagnostics))
+    }
+
     pub fn parse_program(&mut self) -> Result<Program, ParseError> {
         let start_location = self.current_location();
         
         let mut statements = Vec::new();
 
         while self.current_token.is_some() {
-            let stmt = self.parse_statement()?;
-            statements.push(stmt);
+            match self.parse_statement() {
+                Ok(stmt) => statements.push(stmt),
+                Err(error) => self.recover_from(error, false)?,
+            }
         }
 
         let end_location = if let Some(last_token) = self.tokens.clone().last() {
@@ -182,11 +215,15 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Program::new(statements, program_location))
+        let mut program = Program::new(statements, program_location);
+        program.trailing_comments = self.comments.drain(..).collect();
+
+        Ok(program)
     }
 
     fn parse_statement(&mut self) -> Result<Statement, ParseError> {
         let start_location = self.current_location();
+        let mut comments = self.take_comments_before(start_location.start_line, start_location.start_column);
         
         let stmt = match &self.current_token {
             Some(token_with_span) => match &token_with_span.token {
@@ -254,6 +291,7 @@ impl<'a> Parser<'a> {
                     
                     Ok(Statement::ExpressionStatement { 
                         expression: expr,
+                        comments: Vec::new(),
                         location
                     })
                 }
@@ -261,6 +299,11 @@ impl<'a> Parser<'a> {
             None => Err(ParseError::EndOfInput(start_location)),
         }?;
         
+        let mut stmt = stmt;
+        let end_location = stmt.location();
+        comments.extend(self.take_comments_before(end_location.end_line, end_location.end_column));
+        *stmt.comments_mut() = comments;
+        
         Ok(stmt)
     }
     
@@ -292,7 +335,7 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Statement::VariableDeclaration { name, value, location })
+        Ok(Statement::VariableDeclaration { name, value, comments: Vec::new(), location })
     }
 
     fn parse_if_statement(&mut self, start_location: SourceLocation) -> Result<Statement, ParseError> {
@@ -328,6 +371,7 @@ impl<'a> Parser<'a> {
             condition, 
             body,
             else_body,
+            comments: Vec::new(),
             location,
         })
     }
@@ -344,7 +388,7 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Statement::Block { block, location })
+        Ok(Statement::Block { block, comments: Vec::new(), location })
     }
 
     fn parse_block(&mut self) -> Result<Block, ParseError> {
@@ -357,10 +401,14 @@ impl<'a> Parser<'a> {
             if token_with_span.token == Token::CloseBrace {
                 break;
             }
-            statements.push(self.parse_statement()?);
+            match self.parse_statement() {
+                Ok(stmt) => statements.push(stmt),
+                Err(error) => self.recover_from(error, true)?,
+            }
         }
 
         let end_location = self.current_location();
+        let trailing_comments = self.take_comments_before(end_location.start_line, end_location.start_column);
         self.expect(Token::CloseBrace)?;
 
         let location = SourceLocation::new(
@@ -370,7 +418,10 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Block::new(statements, location))
+        let mut block = Block::new(statements, location);
+        block.trailing_comments = trailing_comments;
+
+        Ok(block)
     }
 
     fn parse_expression(&mut self) -> Result<Expression, ParseError> {
@@ -380,7 +431,7 @@ impl<'a> Parser<'a> {
 
     fn parse_assignment(&mut self) -> Result<Expression, ParseError> {
         let start_location = self.current_location();
-        let expr = self.parse_logical_or()?;
+        let expr = self.parse_range()?;
         
         if let Some(token_with_span) = self.current_token {
             if token_with_span.token == Token::Equal {
@@ -389,8 +440,8 @@ impl<'a> Parser<'a> {
                 }
                 
                 self.advance();
-                let value = self.parse_logical_or()?;
-                let end_location = get_expr_location!(value);
+                let value = self.parse_range()?;
+                let end_location = value.location();
                 
                 let location = SourceLocation::new(
                     start_location.start_line,
@@ -427,6 +478,48 @@ impl<'a> Parser<'a> {
         Ok(expr)
     }
 
+    fn parse_range(&mut self) -> Result<Expression, ParseError> {
+        let start_location = self.current_location();
+        let start = self.parse_logical_or()?;
+        
+        let inclusive = match self.current_token {
+            Some(token_with_span) if token_with_span.token == Token::DotDot => false,
+            Some(token_with_span) if token_with_span.token == Token::DotDotEqual => true,
+            _ => return Ok(start),
+        };
+        self.advance();
+        
+        let end = self.parse_logical_or()?;
+        let mut end_location = end.location();
+        
+        // `step` is only a keyword directly after a range, so it stays
+        // usable as a variable name everywhere else.
+        let step = match self.current_token {
+            Some(TokenWithSpan { token: Token::Identifier(name), .. }) if name == "step" => {
+                self.advance();
+                let step = self.parse_logical_or()?;
+                end_location = step.location();
+                Some(Box::new(step))
+            },
+            _ => None,
+        };
+        
+        let location = SourceLocation::new(
+            start_location.start_line,
+            start_location.start_column,
+            end_location.end_line,
+            end_location.end_column
+        );
+        
+        Ok(Expression::RangeExpression {
+            start: Box::new(start),
+            end: Box::new(end),
+            step,
+            inclusive,
+            location,
+        })
+    }
+
     fn parse_logical_or(&mut self) -> Result<Expression, ParseError> {
         let start_location = self.current_location();
         let mut expr = self.parse_logical_and()?;
@@ -436,7 +529,7 @@ impl<'a> Parser<'a> {
                 Token::Or => {
                     self.advance();
                     let right = self.parse_logical_and()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -468,7 +561,7 @@ impl<'a> Parser<'a> {
                 Token::And => {
                     self.advance();
                     let right = self.parse_equality()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -500,7 +593,7 @@ impl<'a> Parser<'a> {
                 Token::Greater => {
                     self.advance();
                     let right = self.parse_additive()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -519,7 +612,7 @@ impl<'a> Parser<'a> {
                 Token::Less => {
                     self.advance();
                     let right = self.parse_additive()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -538,7 +631,7 @@ impl<'a> Parser<'a> {
                 Token::GreaterEqual => {
                     self.advance();
                     let right = self.parse_additive()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -557,7 +650,7 @@ impl<'a> Parser<'a> {
                 Token::LessEqual => {
                     self.advance();
                     let right = self.parse_additive()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -588,7 +681,7 @@ impl<'a> Parser<'a> {
                 Token::EqualEqual => {
                     self.advance();
                     let right = self.parse_comparison()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -607,7 +700,7 @@ impl<'a> Parser<'a> {
                 Token::NotEqual => {
                     self.advance();
                     let right = self.parse_comparison()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -639,7 +732,7 @@ impl<'a> Parser<'a> {
                 Token::Plus => {
                     self.advance();
                     let right = self.parse_multiplicative()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -658,7 +751,7 @@ impl<'a> Parser<'a> {
                 Token::Minus => {
                     self.advance();
                     let right = self.parse_multiplicative()?;
-                    let right_loc = get_expr_location!(right);
+                    let right_loc = right.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -683,59 +776,68 @@ impl<'a> Parser<'a> {
     
     fn parse_multiplicative(&mut self) -> Result<Expression, ParseError> {
         let start_location = self.current_location();
-        let mut expr = self.parse_unary()?;
-        
-        expr = self.parse_member_access(expr)?;
+        let mut expr = self.parse_exponent()?;
 
         while let Some(token_with_span) = self.current_token {
-            match &token_with_span.token {
-                Token::Multiply => {
-                    self.advance();
-                    let right = self.parse_primary()?;
-                    let right_with_member = self.parse_member_access(right)?;
-                    let right_loc = get_expr_location!(right_with_member);
-                    
-                    let location = SourceLocation::new(
-                        start_location.start_line,
-                        start_location.start_column,
-                        right_loc.end_line,
-                        right_loc.end_column
-                    );
-                    
-                    expr = Expression::BinaryExpression {
-                        left: Box::new(expr),
-                        operator: Operator::Multiply,
-                        right: Box::new(right_with_member),
-                        location,
-                    };
-                }
-                Token::Divide => {
-                    self.advance();
-                    let right = self.parse_primary()?;
-                    let right_with_member = self.parse_member_access(right)?;
-                    let right_loc = get_expr_location!(right_with_member);
-                    
-                    let location = SourceLocation::new(
-                        start_location.start_line,
-                        start_location.start_column,
-                        right_loc.end_line,
-                        right_loc.end_column
-                    );
-                    
-                    expr = Expression::BinaryExpression {
-                        left: Box::new(expr),
-                        operator: Operator::Divide,
-                        right: Box::new(right_with_member),
-                        location,
-                    };
-                }
+            let operator = match &token_with_span.token {
+                Token::Multiply => Operator::Multiply,
+                Token::Divide => Operator::Divide,
+                Token::Percent => Operator::Modulo,
+                Token::IntegerDivide => Operator::IntegerDivide,
                 _ => break,
-            }
+            };
+            
+            self.advance();
+            let right = self.parse_exponent()?;
+            let right_loc = right.location();
+            
+            let location = SourceLocation::new(
+                start_location.start_line,
+                start_location.start_column,
+                right_loc.end_line,
+                right_loc.end_column
+            );
+            
+            expr = Expression::BinaryExpression {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+                location,
+            };
         }
 
         Ok(expr)
     }
 
+    fn parse_exponent(&mut self) -> Result<Expression, ParseError> {
+        let start_location = self.current_location();
+        let base = self.parse_unary()?;
+        let base = self.parse_member_access(base)?;
+
+        if matches!(&self.current_token, Some(token_with_span) if token_with_span.token == Token::Power) {
+            self.advance();
+            // Right-associative: `a ** b ** c` is `a ** (b ** c)`.
+            let exponent = self.parse_exponent()?;
+            let exponent_loc = exponent.location();
+            
+            let location = SourceLocation::new(
+                start_location.start_line,
+                start_location.start_column,
+                exponent_loc.end_line,
+                exponent_loc.end_column
+            );
+            
+            return Ok(Expression::BinaryExpression {
+                left: Box::new(base),
+                operator: Operator::Power,
+                right: Box::new(exponent),
+                location,
+            });
+        }
+
+        Ok(base)
+    }
+
     fn parse_unary(&mut self) -> Result<Expression, ParseError> {
         let start_location = self.current_location();
         
@@ -744,7 +846,7 @@ impl<'a> Parser<'a> {
                 Token::Bang => {
                     self.advance();
                     let operand = self.parse_unary()?;
-                    let operand_loc = get_expr_location!(operand);
+                    let operand_loc = operand.location();
                     
                     let location = SourceLocation::new(
                         start_location.start_line,
@@ -759,6 +861,30 @@ impl<'a> Parser<'a> {
                         location,
                     })
                 },
+                Token::Minus | Token::Plus => {
+                    let operator = if token_with_span.token == Token::Minus {
+                        UnaryOperator::Negate
+                    } else {
+                        UnaryOperator::Plus
+                    };
+                    self.advance();
+                    // The operand includes any `**`, so `-2 ** 2` is `-(2 ** 2)`.
+                    let operand = self.parse_exponent()?;
+                    let operand_loc = operand.location();
+                    
+                    let location = SourceLocation::new(
+                        start_location.start_line,
+                        start_location.start_column,
+                        operand_loc.end_line,
+                        operand_loc.end_column
+                    );
+                    
+                    Ok(Expression::UnaryExpression {
+                        operator,
+                        operand: Box::new(operand),
+                        location,
+                    })
+                },
                 _ => self.parse_primary()
             },
             None => Err(ParseError::EndOfInput(start_location)),
@@ -780,7 +906,7 @@ impl<'a> Parser<'a> {
                         // Then handle any member access operations after it
                         object_expr = self.parse_member_access(object_expr)?;
                         
-                        let object_loc = get_expr_location!(object_expr);
+                        let object_loc = object_expr.location();
                         
                         let location = SourceLocation::new(
                             start_location.start_line,
@@ -990,6 +1116,7 @@ impl<'a> Parser<'a> {
         self.advance(); // consume 'cb'
 
         // Check if next token is an identifier (name) or opening parenthesis (nameless)
+        let anonymous = matches!(&self.current_token, Some(token_with_span) if token_with_span.token == Token::OpenParen);
         let name = match &self.current_token {
             Some(token_with_span) => match &token_with_span.token {
                 Token::Identifier(name) => {
@@ -1063,6 +1190,7 @@ impl<'a> Parser<'a> {
 
         Ok(Expression::InlineCallbackExpression {
             name,
+            anonymous,
             params,
             body,
             location,
@@ -1073,6 +1201,7 @@ impl<'a> Parser<'a> {
         self.advance();
 
         // Check if next token is an identifier (name) or opening parenthesis (nameless)
+        let anonymous = matches!(&self.current_token, Some(token_with_span) if token_with_span.token == Token::OpenParen);
         let name = match &self.current_token {
             Some(token_with_span) => match &token_with_span.token {
                 Token::Identifier(name) => {
@@ -1146,8 +1275,10 @@ impl<'a> Parser<'a> {
 
         Ok(Statement::CallbackDeclaration {
             name,
+            anonymous,
             params,
             body,
+            comments: Vec::new(),
             location,
         })
     }
@@ -1169,7 +1300,7 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Statement::ReturnStatement { value, location })
+        Ok(Statement::ReturnStatement { value, comments: Vec::new(), location })
     }
 
     fn parse_array_literal(&mut self, start_location: SourceLocation) -> Result<Expression, ParseError> {
@@ -1289,7 +1420,7 @@ impl<'a> Parser<'a> {
         
         let value = self.parse_expression()?;
         
-        let end_location = get_expr_location!(value);
+        let end_location = value.location();
         
         let location = SourceLocation::new(
             start_location.start_line,
@@ -1319,8 +1450,8 @@ impl<'a> Parser<'a> {
                                     let property_location = self.current_location();
                                     self.advance();
                                     
-                                    let obj_start_line = get_expr_start_line!(object);
-                                    let obj_start_column = get_expr_start_column!(object);
+                                    let obj_start_line = object.location().start_line;
+                                    let obj_start_column = object.location().start_column;
                                     
                                     if let Some(token_with_span) = self.current_token {
                                         if token_with_span.token == Token::OpenParen {
@@ -1466,8 +1597,8 @@ impl<'a> Parser<'a> {
                         let close_bracket_location = self.current_location();
                         self.expect(Token::CloseBracket)?;
                         
-                        let obj_start_line = get_expr_start_line!(object);
-                        let obj_start_column = get_expr_start_column!(object);
+                        let obj_start_line = object.location().start_line;
+                        let obj_start_column = object.location().start_column;
                         
                         let member_expr_location = SourceLocation::new(
                             obj_start_line,
@@ -1599,14 +1730,21 @@ impl<'a> Parser<'a> {
     fn parse_loop_statement(&mut self, start_location: SourceLocation) -> Result<Statement, ParseError> {
         self.advance();
         
-        let variable = match &self.current_token {
-            Some(token_with_span) => match &token_with_span.token {
-                Token::Identifier(name) => name.clone(),
-                _ => return Err(ParseError::ExpectedToken("identifier".to_string(), self.current_location())),
-            },
-            None => return Err(ParseError::EndOfInput(self.current_location())),
-        };
-        self.advance();
+        if matches!(&self.current_token, Some(token_with_span) if token_with_span.token == Token::While) {
+            if !self.flags.allow_while_loops {
+                return Err(ParseError::FeatureDisabled("Conditional loops".to_string(), start_location));
+            }
+            return self.parse_while_loop_statement(start_location);
+        }
+        
+        let mut key_variable = None;
+        let mut variable = self.parse_loop_variable()?;
+        
+        if matches!(&self.current_token, Some(token_with_span) if token_with_span.token == Token::Comma) {
+            self.advance();
+            key_variable = Some(variable);
+            variable = self.parse_loop_variable()?;
+        }
         
         match &self.current_token {
             Some(token_with_span) => {
@@ -1632,9 +1770,48 @@ impl<'a> Parser<'a> {
         );
 
         Ok(Statement::LoopStatement {
+            key_variable,
             variable,
             iterable,
             body,
+            comments: Vec::new(),
+            location,
+        })
+    }
+
+    fn parse_loop_variable(&mut self) -> Result<String, ParseError> {
+        let variable = match &self.current_token {
+            Some(token_with_span) => match &token_with_span.token {
+                Token::Identifier(name) => name.clone(),
+                _ => return Err(ParseError::ExpectedToken("identifier".to_string(), self.current_location())),
+            },
+            None => return Err(ParseError::EndOfInput(self.current_location())),
+        };
+        self.advance();
+        
+        Ok(variable)
+    }
+
+    fn parse_while_loop_statement(&mut self, start_location: SourceLocation) -> Result<Statement, ParseError> {
+        self.advance();
+        
+        let condition = self.parse_expression()?;
+        
+        let body = self.parse_block()?;
+        
+        let end_location = body.location;
+        
+        let location = SourceLocation::new(
+            start_location.start_line,
+            start_location.start_column,
+            end_location.end_line,
+            end_location.end_column
+        );
+
+        Ok(Statement::WhileLoopStatement {
+            condition,
+            body,
+            comments: Vec::new(),
             location,
         })
     }
@@ -1654,7 +1831,7 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Statement::EndStatement { location })
+        Ok(Statement::EndStatement { comments: Vec::new(), location })
     }
     
     fn parse_continue_statement(&mut self, start_location: SourceLocation) -> Result<Statement, ParseError> {
@@ -1672,6 +1849,21 @@ impl<'a> Parser<'a> {
             end_location.end_column
         );
 
-        Ok(Statement::ContinueStatement { location })
+        Ok(Statement::ContinueStatement { comments: Vec::new(), location })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::feature_flags::FeatureFlags;
+
+    #[test]
+    fn recovers_after_errors_to_report_each_of_them() {
+        let code = "vl a = ;\nvl b = 2;\nres (b;\nres b;";
+        let (program, diagnostics) = crate::process_code_with_diagnostics(code, FeatureFlags::default());
+
+        let lines: Vec<_> = diagnostics.iter().map(|d| d.location.start_line).collect();
+        assert_eq!(lines, [1, 3]);
+        assert_eq!(program.statements.len(), 2);
     }
 }
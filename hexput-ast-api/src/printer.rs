@@ -0,0 +1,506 @@
+//! Turns an AST back into Hexput source code.
+//!
+//! The output is canonical: one statement per line, four spaces of
+//! indentation per block and parentheses only where the grammar needs them.
+//! A single blank line is kept between statements that were separated by
+//! blank lines in the source, when the AST has source locations. Comments
+//! attached to the AST are printed on lines of their own.
+
+use crate::ast_structs::{Block, Comment, Expression, Operator, Program, Property, SourceLocation, Statement, UnaryOperator};
+
+const INDENT: &str = "    ";
+
+const KEYWORDS: &[&str] = &[
+    "vl", "if", "else", "cb", "res", "loop", "in", "while", "end", "continue", "keysof", "true", "false", "null",
+];
+
+// Binding strength of each level of the grammar, loosest first. An operand
+// is wrapped in parentheses when it binds looser than its position allows.
+const ASSIGNMENT: u8 = 1;
+const RANGE: u8 = 2;
+const OR: u8 = 3;
+const AND: u8 = 4;
+const EQUALITY: u8 = 5;
+const COMPARISON: u8 = 6;
+const ADDITIVE: u8 = 7;
+const MULTIPLICATIVE: u8 = 8;
+const EXPONENT: u8 = 9;
+const UNARY: u8 = 10;
+const POSTFIX: u8 = 11;
+
+/// Prints a whole program, ending with a newline unless it is empty.
+pub fn print_program(program: &Program) -> String {
+    let mut printer = Printer::default();
+    printer.statements(&program.statements, &program.trailing_comments);
+    printer.output
+}
+
+pub fn print_statement(statement: &Statement) -> String {
+    let mut printer = Printer::default();
+    printer.statement(statement);
+    printer.output
+}
+
+pub fn print_expression(expression: &Expression) -> String {
+    let mut printer = Printer::default();
+    printer.expression(expression, 0);
+    printer.output
+}
+
+#[derive(Default)]
+struct Printer {
+    output: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn write(&mut self, text: &str) {
+        self.output.push_str(text);
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.output.push_str(INDENT);
+        }
+    }
+
+    fn statements(&mut self, statements: &[Statement], trailing_comments: &[Comment]) {
+        let mut previous_end_line = None;
+
+        for statement in statements {
+            for comment in statement.comments() {
+                self.line_break(&mut previous_end_line, comment.location());
+                self.comment(comment);
+            }
+
+            self.line_break(&mut previous_end_line, statement.location());
+            self.statement(statement);
+        }
+
+        for comment in trailing_comments {
+            self.line_break(&mut previous_end_line, comment.location());
+            self.comment(comment);
+        }
+
+        if previous_end_line.is_some() {
+            self.write("\n");
+        }
+    }
+
+    /// Ends the previous line, if any, and indents the next one. Adds a blank
+    /// line when the source had one before `location`.
+    fn line_break(&mut self, previous_end_line: &mut Option<usize>, location: SourceLocation) {
+        if let Some(end_line) = *previous_end_line {
+            self.write("\n");
+            if location.start_line > end_line + 1 {
+                self.write("\n");
+            }
+        }
+        *previous_end_line = Some(location.end_line);
+
+        self.write_indent();
+    }
+
+    fn comment(&mut self, comment: &Comment) {
+        match comment {
+            Comment::Line { text, .. } => {
+                self.write("//");
+                self.write(text);
+            },
+            Comment::Block { text, .. } => {
+                self.write("/*");
+                self.write(text);
+                self.write("*/");
+            },
+        }
+    }
+
+    fn block(&mut self, block: &Block) {
+        if block.statements.is_empty() && block.trailing_comments.is_empty() {
+            self.write("{}");
+            return;
+        }
+
+        self.write("{\n");
+        self.indent += 1;
+        self.statements(&block.statements, &block.trailing_comments);
+        self.indent -= 1;
+        self.write_indent();
+        self.write("}");
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::VariableDeclaration { name, value, .. } => {
+                self.write("vl ");
+                self.write(name);
+                self.write(" = ");
+                self.expression(value, 0);
+                self.write(";");
+            },
+            Statement::ExpressionStatement { expression, .. } => {
+                // A statement starting with `{` would be read as a block.
+                if starts_with_object(expression) {
+                    self.write("(");
+                    self.expression(expression, 0);
+                    self.write(")");
+                } else {
+                    self.expression(expression, 0);
+                }
+                self.write(";");
+            },
+            Statement::IfStatement { condition, body, else_body, .. } => {
+                self.write("if ");
+                self.expression(condition, 0);
+                self.write(" ");
+                self.block(body);
+                if let Some(else_body) = else_body {
+                    self.write(" else ");
+                    self.block(else_body);
+                }
+            },
+            Statement::Block { block, .. } => self.block(block),
+            Statement::CallbackDeclaration { name, anonymous, params, body, .. } => {
+                self.callback(name, *anonymous, params, body);
+            },
+            Statement::ReturnStatement { value, .. } => {
+                self.write("res ");
+                self.expression(value, 0);
+                self.write(";");
+            },
+            Statement::LoopStatement { key_variable, variable, iterable, body, .. } => {
+                self.write("loop ");
+                if let Some(key_variable) = key_variable {
+                    self.write(key_variable);
+                    self.write(", ");
+                }
+                self.write(variable);
+                self.write(" in ");
+                self.expression(iterable, 0);
+                self.write(" ");
+                self.block(body);
+            },
+            Statement::WhileLoopStatement { condition, body, .. } => {
+                self.write("loop while ");
+                self.expression(condition, 0);
+                self.write(" ");
+                self.block(body);
+            },
+            Statement::EndStatement { .. } => self.write("end;"),
+            Statement::ContinueStatement { .. } => self.write("continue;"),
+        }
+    }
+
+    fn callback(&mut self, name: &str, anonymous: bool, params: &[String], body: &Block) {
+        self.write("cb");
+        if !anonymous {
+            self.write(" ");
+            self.write(name);
+        }
+        self.write("(");
+        self.write(&params.join(", "));
+        self.write(") ");
+        self.block(body);
+    }
+
+    /// Prints `expression`, in parentheses if it binds looser than `min_precedence`.
+    fn expression(&mut self, expression: &Expression, min_precedence: u8) {
+        let parenthesized = precedence(expression) < min_precedence;
+        if parenthesized {
+            self.write("(");
+        }
+
+        match expression {
+            Expression::StringLiteral { value, .. } => self.string(value),
+            Expression::NumberLiteral { value, .. } => self.write(&value.to_string()),
+            Expression::Identifier { name, .. } => self.write(name),
+            Expression::BooleanLiteral { value, .. } => self.write(if *value { "true" } else { "false" }),
+            Expression::NullLiteral { .. } => self.write("null"),
+            Expression::BinaryExpression { left, operator, right, .. } => {
+                let operator_precedence = binary_precedence(operator);
+                // `**` is right-associative and its base cannot be a unary
+                // expression; every other operator is left-associative.
+                let (left_precedence, right_precedence) = if *operator == Operator::Power {
+                    (POSTFIX, EXPONENT)
+                } else {
+                    (operator_precedence, operator_precedence + 1)
+                };
+
+                self.expression(left, left_precedence);
+                self.write(" ");
+                self.write(binary_operator(operator));
+                self.write(" ");
+                self.expression(right, right_precedence);
+            },
+            Expression::UnaryExpression { operator, operand, .. } => {
+                let (symbol, operand_precedence) = match operator {
+                    UnaryOperator::Not => ("!", UNARY),
+                    UnaryOperator::Negate => ("-", EXPONENT),
+                    UnaryOperator::Plus => ("+", EXPONENT),
+                };
+                self.write(symbol);
+
+                // Member access after `!x` applies to the negation, so a
+                // member operand of `!` needs parentheses. Nested signs are
+                // parenthesized to keep them readable.
+                let needs_parentheses = match operator {
+                    UnaryOperator::Not => is_member_access(operand),
+                    UnaryOperator::Negate | UnaryOperator::Plus => precedence(operand) == UNARY,
+                };
+
+                if needs_parentheses {
+                    self.write("(");
+                    self.expression(operand, 0);
+                    self.write(")");
+                } else {
+                    self.expression(operand, operand_precedence);
+                }
+            },
+            Expression::AssignmentExpression { target, value, .. } => {
+                self.write(target);
+                self.write(" = ");
+                self.expression(value, RANGE);
+            },
+            Expression::MemberAssignmentExpression { object, property, property_expr, value, .. } => {
+                self.member(object, property.as_deref(), property_expr.as_deref());
+                self.write(" = ");
+                self.expression(value, RANGE);
+            },
+            Expression::MemberExpression { object, property, property_expr, .. } => {
+                self.member(object, property.as_deref(), property_expr.as_deref());
+            },
+            Expression::CallExpression { callee, arguments, .. } => {
+                self.write(callee);
+                self.arguments(arguments);
+            },
+            Expression::MemberCallExpression { object, property, property_expr, arguments, .. } => {
+                self.member(object, property.as_deref(), property_expr.as_deref());
+                self.arguments(arguments);
+            },
+            Expression::InlineCallbackExpression { name, anonymous, params, body, .. } => {
+                self.callback(name, *anonymous, params, body);
+            },
+            Expression::ArrayExpression { elements, .. } => {
+                self.write("[");
+                self.list(elements, |printer, element| printer.expression(element, 0));
+                self.write("]");
+            },
+            Expression::ObjectExpression { properties, .. } => {
+                if properties.is_empty() {
+                    self.write("{}");
+                } else {
+                    self.write("{ ");
+                    self.list(properties, Self::property);
+                    self.write(" }");
+                }
+            },
+            Expression::KeysOfExpression { object, .. } => {
+                self.write("keysof ");
+                self.expression(object, POSTFIX);
+            },
+            Expression::RangeExpression { start, end, step, inclusive, .. } => {
+                self.expression(start, OR);
+                self.write(if *inclusive { "..=" } else { ".." });
+                self.expression(end, OR);
+                if let Some(step) = step {
+                    self.write(" step ");
+                    self.expression(step, OR);
+                }
+            },
+        }
+
+        if parenthesized {
+            self.write(")");
+        }
+    }
+
+    fn member(&mut self, object: &Expression, property: Option<&str>, property_expr: Option<&Expression>) {
+        self.expression(object, POSTFIX);
+
+        match (property, property_expr) {
+            (Some(property), _) if is_identifier(property) => {
+                self.write(".");
+                self.write(property);
+            },
+            (Some(property), _) => {
+                self.write("[");
+                self.string(property);
+                self.write("]");
+            },
+            (None, Some(property_expr)) => {
+                self.write("[");
+                self.expression(property_expr, 0);
+                self.write("]");
+            },
+            (None, None) => {},
+        }
+    }
+
+    fn arguments(&mut self, arguments: &[Expression]) {
+        self.write("(");
+        self.list(arguments, |printer, argument| printer.expression(argument, 0));
+        self.write(")");
+    }
+
+    fn property(&mut self, property: &Property) {
+        if is_identifier(&property.key) {
+            self.write(&property.key);
+        } else {
+            self.string(&property.key);
+        }
+        self.write(": ");
+        self.expression(&property.value, 0);
+    }
+
+    fn list<T>(&mut self, items: &[T], mut print_item: impl FnMut(&mut Self, &T)) {
+        for (index, item) in items.iter().enumerate() {
+            if index > 0 {
+                self.write(", ");
+            }
+            print_item(self, item);
+        }
+    }
+
+    fn string(&mut self, value: &str) {
+        self.output.push('"');
+        for c in value.chars() {
+            match c {
+                '"' => self.write("\\\""),
+                '\\' => self.write("\\\\"),
+                '\n' => self.write("\\n"),
+                '\t' => self.write("\\t"),
+                '\r' => self.write("\\r"),
+                _ => self.output.push(c),
+            }
+        }
+        self.output.push('"');
+    }
+}
+
+fn precedence(expression: &Expression) -> u8 {
+    match expression {
+        Expression::AssignmentExpression { .. } | Expression::MemberAssignmentExpression { .. } => ASSIGNMENT,
+        Expression::RangeExpression { .. } => RANGE,
+        Expression::BinaryExpression { operator, .. } => binary_precedence(operator),
+        Expression::UnaryExpression { .. } | Expression::KeysOfExpression { .. } => UNARY,
+        // Negative numbers only come from folding and are printed with a sign.
+        Expression::NumberLiteral { value, .. } if value.is_sign_negative() => UNARY,
+        // Inline callbacks can only be parsed as call arguments.
+        Expression::InlineCallbackExpression { .. } => 0,
+        _ => POSTFIX,
+    }
+}
+
+fn binary_precedence(operator: &Operator) -> u8 {
+    match operator {
+        Operator::Or => OR,
+        Operator::And => AND,
+        Operator::Equal | Operator::NotEqual => EQUALITY,
+        Operator::Greater | Operator::Less | Operator::GreaterEqual | Operator::LessEqual => COMPARISON,
+        Operator::Plus | Operator::Minus => ADDITIVE,
+        Operator::Multiply | Operator::Divide | Operator::Modulo | Operator::IntegerDivide => MULTIPLICATIVE,
+        Operator::Power => EXPONENT,
+    }
+}
+
+fn binary_operator(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::Equal => "==",
+        Operator::NotEqual => "!=",
+        Operator::Plus => "+",
+        Operator::Minus => "-",
+        Operator::Multiply => "*",
+        Operator::Divide => "/",
+        Operator::Modulo => "%",
+        Operator::Power => "**",
+        Operator::IntegerDivide => "~/",
+        Operator::Greater => ">",
+        Operator::Less => "<",
+        Operator::GreaterEqual => ">=",
+        Operator::LessEqual => "<=",
+        Operator::And => "&&",
+        Operator::Or => "||",
+    }
+}
+
+fn is_member_access(expression: &Expression) -> bool {
+    matches!(expression, Expression::MemberExpression { .. } | Expression::MemberCallExpression { .. })
+}
+
+/// Whether the printed expression begins with an object literal.
+fn starts_with_object(expression: &Expression) -> bool {
+    // Operands that get parenthesized start with `(` instead.
+    let leads = |operand: &Expression, min_precedence: u8| {
+        precedence(operand) >= min_precedence && starts_with_object(operand)
+    };
+
+    match expression {
+        Expression::ObjectExpression { .. } => true,
+        Expression::BinaryExpression { left, operator: Operator::Power, .. } => leads(left, POSTFIX),
+        Expression::BinaryExpression { left, operator, .. } => leads(left, binary_precedence(operator)),
+        Expression::RangeExpression { start, .. } => leads(start, OR),
+        Expression::MemberExpression { object, .. } |
+        Expression::MemberCallExpression { object, .. } |
+        Expression::MemberAssignmentExpression { object, .. } => leads(object, POSTFIX),
+        _ => false,
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    let starts_well = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+
+    starts_well && chars.all(|c| c.is_ascii_alphanumeric() || c == '_') && !KEYWORDS.contains(&name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature_flags::FeatureFlags;
+
+    fn format(code: &str) -> String {
+        print_program(&crate::parse_code(code, FeatureFlags::default()).unwrap())
+    }
+
+    #[test]
+    fn prints_canonical_source() {
+        assert_eq!(
+            format("vl x=5;if x>1{print( x );}else{res [1,2];}"),
+            "vl x = 5;\nif x > 1 {\n    print(x);\n} else {\n    res [1, 2];\n}\n"
+        );
+    }
+
+    #[test]
+    fn parenthesizes_only_where_needed() {
+        assert_eq!(format("res (1 + 2) * 3;"), "res (1 + 2) * 3;\n");
+        assert_eq!(format("res 1 + (2 * 3);"), "res 1 + 2 * 3;\n");
+        assert_eq!(format("res (2 ** 3) ** 2;"), "res (2 ** 3) ** 2;\n");
+        assert_eq!(format("res -(a.b);"), "res -a.b;\n");
+    }
+
+    #[test]
+    fn printed_code_parses_to_the_same_program() {
+        let code = "vl o = { a: 1, \"b c\": [1..=3 step 2] };\n\nloop k, v in o { if !v { continue; } }\ncb f(a) { res a ~/ 2 % 3; }\nres f(o[\"b c\"].length());";
+        let formatted = format(code);
+        let ast = |code: &str| {
+            let program = crate::parse_code(code, FeatureFlags::default()).unwrap();
+            crate::to_json_string(&program, false).unwrap()
+        };
+        assert_eq!(ast(&formatted), ast(code));
+        assert_eq!(format(&formatted), formatted);
+        assert!(formatted.contains("\n\nloop"));
+    }
+
+    #[test]
+    fn keeps_comments_in_lossless_mode() {
+        let code = "// setup\nvl a = 1;\nif a {\n    /* inner */\n    print(a);\n}\n// done\n";
+        let program = crate::parse_code_with_comments(code, FeatureFlags::default()).unwrap();
+        assert!(matches!(
+            program.statements[0].comments(),
+            [Comment::Line { text, location }] if text == " setup" && location.start_line == 1 && location.end_column == 9
+        ));
+        assert_eq!(program.trailing_comments.len(), 1);
+        assert_eq!(print_program(&program), code);
+        assert_eq!(format(code), "vl a = 1;\nif a {\n    print(a);\n}\n");
+    }
+}
@@ -0,0 +1,319 @@
+//! Traversal of the AST.
+//!
+//! Implement `Visitor` (or `VisitorMut` to modify nodes in place) and
+//! override the methods for the nodes of interest. Every method defaults to
+//! the matching `walk_*` function, which visits the children of the node, so
+//! an override calls it to keep descending.
+
+use crate::ast_structs::{Block, Expression, Program, Property, Statement};
+
+pub trait Visitor<'ast> {
+    fn visit_program(&mut self, program: &'ast Program) {
+        walk_program(self, program);
+    }
+
+    fn visit_block(&mut self, block: &'ast Block) {
+        walk_block(self, block);
+    }
+
+    fn visit_statement(&mut self, statement: &'ast Statement) {
+        walk_statement(self, statement);
+    }
+
+    fn visit_expression(&mut self, expression: &'ast Expression) {
+        walk_expression(self, expression);
+    }
+
+    fn visit_property(&mut self, property: &'ast Property) {
+        walk_property(self, property);
+    }
+}
+
+pub fn walk_program<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, program: &'ast Program) {
+    for statement in &program.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_block<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, block: &'ast Block) {
+    for statement in &block.statements {
+        visitor.visit_statement(statement);
+    }
+}
+
+pub fn walk_statement<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, statement: &'ast Statement) {
+    match statement {
+        Statement::VariableDeclaration { value, .. } => visitor.visit_expression(value),
+        Statement::ExpressionStatement { expression, .. } => visitor.visit_expression(expression),
+        Statement::IfStatement { condition, body, else_body, .. } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(body);
+            if let Some(else_body) = else_body {
+                visitor.visit_block(else_body);
+            }
+        },
+        Statement::Block { block, .. } => visitor.visit_block(block),
+        Statement::CallbackDeclaration { body, .. } => visitor.visit_block(body),
+        Statement::ReturnStatement { value, .. } => visitor.visit_expression(value),
+        Statement::LoopStatement { iterable, body, .. } => {
+            visitor.visit_expression(iterable);
+            visitor.visit_block(body);
+        },
+        Statement::WhileLoopStatement { condition, body, .. } => {
+            visitor.visit_expression(condition);
+            visitor.visit_block(body);
+        },
+        Statement::EndStatement { .. } | Statement::ContinueStatement { .. } => {},
+    }
+}
+
+pub fn walk_expression<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, expression: &'ast Expression) {
+    match expression {
+        Expression::BinaryExpression { left, right, .. } => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        },
+        Expression::AssignmentExpression { value, .. } => visitor.visit_expression(value),
+        Expression::MemberAssignmentExpression { object, property_expr, value, .. } => {
+            visitor.visit_expression(object);
+            if let Some(property_expr) = property_expr {
+                visitor.visit_expression(property_expr);
+            }
+            visitor.visit_expression(value);
+        },
+        Expression::CallExpression { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        },
+        Expression::MemberCallExpression { object, property_expr, arguments, .. } => {
+            visitor.visit_expression(object);
+            if let Some(property_expr) = property_expr {
+                visitor.visit_expression(property_expr);
+            }
+            for argument in arguments {
+                visitor.visit_expression(argument);
+            }
+        },
+        Expression::InlineCallbackExpression { body, .. } => visitor.visit_block(body),
+        Expression::ArrayExpression { elements, .. } => {
+            for element in elements {
+                visitor.visit_expression(element);
+            }
+        },
+        Expression::ObjectExpression { properties, .. } => {
+            for property in properties {
+                visitor.visit_property(property);
+            }
+        },
+        Expression::MemberExpression { object, property_expr, .. } => {
+            visitor.visit_expression(object);
+            if let Some(property_expr) = property_expr {
+                visitor.visit_expression(property_expr);
+            }
+        },
+        Expression::KeysOfExpression { object, .. } => visitor.visit_expression(object),
+        Expression::UnaryExpression { operand, .. } => visitor.visit_expression(operand),
+        Expression::RangeExpression { start, end, step, .. } => {
+            visitor.visit_expression(start);
+            visitor.visit_expression(end);
+            if let Some(step) = step {
+                visitor.visit_expression(step);
+            }
+        },
+        Expression::StringLiteral { .. } |
+        Expression::NumberLiteral { .. } |
+        Expression::Identifier { .. } |
+        Expression::BooleanLiteral { .. } |
+        Expression::NullLiteral { .. } => {},
+    }
+}
+
+pub fn walk_property<'ast, V: Visitor<'ast> + ?Sized>(visitor: &mut V, property: &'ast Property) {
+    visitor.visit_expression(&property.value);
+}
+
+pub trait VisitorMut {
+    fn visit_program_mut(&mut self, program: &mut Program) {
+        walk_program_mut(self, program);
+    }
+
+    fn visit_block_mut(&mut self, block: &mut Block) {
+        walk_block_mut(self, block);
+    }
+
+    fn visit_statement_mut(&mut self, statement: &mut Statement) {
+        walk_statement_mut(self, statement);
+    }
+
+    fn visit_expression_mut(&mut self, expression: &mut Expression) {
+        walk_expression_mut(self, expression);
+    }
+
+    fn visit_property_mut(&mut self, property: &mut Property) {
+        walk_property_mut(self, property);
+    }
+}
+
+pub fn walk_program_mut<V: VisitorMut + ?Sized>(visitor: &mut V, program: &mut Program) {
+    for statement in &mut program.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_block_mut<V: VisitorMut + ?Sized>(visitor: &mut V, block: &mut Block) {
+    for statement in &mut block.statements {
+        visitor.visit_statement_mut(statement);
+    }
+}
+
+pub fn walk_statement_mut<V: VisitorMut + ?Sized>(visitor: &mut V, statement: &mut Statement) {
+    match statement {
+        Statement::VariableDeclaration { value, .. } => visitor.visit_expression_mut(value),
+        Statement::ExpressionStatement { expression, .. } => visitor.visit_expression_mut(expression),
+        Statement::IfStatement { condition, body, else_body, .. } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_block_mut(body);
+            if let Some(else_body) = else_body {
+                visitor.visit_block_mut(else_body);
+            }
+        },
+        Statement::Block { block, .. } => visitor.visit_block_mut(block),
+        Statement::CallbackDeclaration { body, .. } => visitor.visit_block_mut(body),
+        Statement::ReturnStatement { value, .. } => visitor.visit_expression_mut(value),
+        Statement::LoopStatement { iterable, body, .. } => {
+            visitor.visit_expression_mut(iterable);
+            visitor.visit_block_mut(body);
+        },
+        Statement::WhileLoopStatement { condition, body, .. } => {
+            visitor.visit_expression_mut(condition);
+            visitor.visit_block_mut(body);
+        },
+        Statement::EndStatement { .. } | Statement::ContinueStatement { .. } => {},
+    }
+}
+
+pub fn walk_expression_mut<V: VisitorMut + ?Sized>(visitor: &mut V, expression: &mut Expression) {
+    match expression {
+        Expression::BinaryExpression { left, right, .. } => {
+            visitor.visit_expression_mut(left);
+            visitor.visit_expression_mut(right);
+        },
+        Expression::AssignmentExpression { value, .. } => visitor.visit_expression_mut(value),
+        Expression::MemberAssignmentExpression { object, property_expr, value, .. } => {
+            visitor.visit_expression_mut(object);
+            if let Some(property_expr) = property_expr {
+                visitor.visit_expression_mut(property_expr);
+            }
+            visitor.visit_expression_mut(value);
+        },
+        Expression::CallExpression { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        },
+        Expression::MemberCallExpression { object, property_expr, arguments, .. } => {
+            visitor.visit_expression_mut(object);
+            if let Some(property_expr) = property_expr {
+                visitor.visit_expression_mut(property_expr);
+            }
+            for argument in arguments {
+                visitor.visit_expression_mut(argument);
+            }
+        },
+        Expression::InlineCallbackExpression { body, .. } => visitor.visit_block_mut(body),
+        Expression::ArrayExpression { elements, .. } => {
+            for element in elements {
+                visitor.visit_expression_mut(element);
+            }
+        },
+        Expression::ObjectExpression { properties, .. } => {
+            for property in properties {
+                visitor.visit_property_mut(property);
+            }
+        },
+        Expression::MemberExpression { object, property_expr, .. } => {
+            visitor.visit_expression_mut(object);
+            if let Some(property_expr) = property_expr {
+                visitor.visit_expression_mut(property_expr);
+            }
+        },
+        Expression::KeysOfExpression { object, .. } => visitor.visit_expression_mut(object),
+        Expression::UnaryExpression { operand, .. } => visitor.visit_expression_mut(operand),
+        Expression::RangeExpression { start, end, step, .. } => {
+            visitor.visit_expression_mut(start);
+            visitor.visit_expression_mut(end);
+            if let Some(step) = step {
+                visitor.visit_expression_mut(step);
+            }
+        },
+        Expression::StringLiteral { .. } |
+        Expression::NumberLiteral { .. } |
+        Expression::Identifier { .. } |
+        Expression::BooleanLiteral { .. } |
+        Expression::NullLiteral { .. } => {},
+    }
+}
+
+pub fn walk_property_mut<V: VisitorMut + ?Sized>(visitor: &mut V, property: &mut Property) {
+    visitor.visit_expression_mut(&mut property.value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature_flags::FeatureFlags;
+
+    fn parse(code: &str) -> Program {
+        let tokens = crate::lexer::tokenize(code).unwrap();
+        crate::parser::Parser::new(&tokens, FeatureFlags::default(), code).parse_program().unwrap()
+    }
+
+    struct Identifiers(Vec<String>);
+
+    impl<'ast> Visitor<'ast> for Identifiers {
+        fn visit_expression(&mut self, expression: &'ast Expression) {
+            if let Expression::Identifier { name, .. } = expression {
+                self.0.push(name.clone());
+            }
+            walk_expression(self, expression);
+        }
+    }
+
+    struct Rename;
+
+    impl VisitorMut for Rename {
+        fn visit_expression_mut(&mut self, expression: &mut Expression) {
+            if let Expression::Identifier { name, .. } = expression {
+                name.make_ascii_uppercase();
+            }
+            walk_expression_mut(self, expression);
+        }
+    }
+
+    #[test]
+    fn visits_nested_nodes_in_source_order() {
+        let mut program = parse("vl o = { a: b }; if c { loop x in d { run(x, cb (y) { res e[y]; }); } }");
+
+        let mut identifiers = Identifiers(Vec::new());
+        identifiers.visit_program(&program);
+        assert_eq!(identifiers.0, ["b", "c", "d", "x", "e", "y"]);
+
+        Rename.visit_program_mut(&mut program);
+        let mut identifiers = Identifiers(Vec::new());
+        identifiers.visit_program(&program);
+        assert_eq!(identifiers.0, ["B", "C", "D", "X", "E", "Y"]);
+    }
+
+    #[test]
+    fn nodes_report_their_location() {
+        let program = parse("vl a = 1;\nres a + 2;");
+        let location = program.statements[1].location();
+        assert_eq!((location.start_line, location.start_column), (2, 1));
+
+        match &program.statements[1] {
+            Statement::ReturnStatement { value, .. } => assert_eq!(value.location().start_column, 5),
+            other => panic!("expected a return statement, got {:?}", other),
+        }
+    }
+}
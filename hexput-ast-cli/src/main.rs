@@ -1,5 +1,7 @@
+use hexput_ast_api::analysis::{FunctionSignature, HostEnvironment};
+use hexput_ast_api::diagnostics::Severity;
 use hexput_ast_api::feature_flags::FeatureFlags;
-use clap::{Arg, Command, ArgAction};
+use clap::{Arg, ArgMatches, Command, ArgAction};
 use std::env;
 use std::process;
 
@@ -13,70 +15,132 @@ fn main() {
         .arg(Arg::new("minify")
             .long("minify")
             .help("Minify the output JSON (remove whitespace)")
+            .global(true)
             .action(ArgAction::SetTrue))
         .arg(Arg::new("no-object-constructions")
             .long("no-object-constructions")
             .help("Disable object literal construction")
+            .global(true)
             .action(ArgAction::SetTrue))
         .arg(Arg::new("no-array-constructions")
             .long("no-array-constructions")
             .help("Disable array literal construction")
+            .global(true)
             .action(ArgAction::SetTrue))
         .arg(Arg::new("no-object-navigation")
             .long("no-object-navigation")
             .help("Disable object property access (dot notation and bracket notation)")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-variable-declaration")
             .long("no-variable-declaration")
             .help("Disable variable declarations with 'vl'")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-loops")
             .long("no-loops")
             .help("Disable loop statements")
+            .global(true)
+            .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("no-while-loops")
+            .long("no-while-loops")
+            .help("Disable conditional loops (loop while)")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-object-keys")
             .long("no-object-keys")
             .help("Disable keysof operator")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-callbacks")
             .long("no-callbacks")
             .help("Disable callback declarations")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-conditionals")
             .long("no-conditionals")
             .help("Disable if statements")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-return-statements")
             .long("no-return-statements")
             .help("Disable return statements with 'res'")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-loop-control")
             .long("no-loop-control")
             .help("Disable loop control statements (end, continue)")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-operators")
             .long("no-operators")
             .help("Disable arithmetic operators (+, *, /)")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-equality")
             .long("no-equality")
             .help("Disable equality operator (==)")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
         .arg(Arg::new("no-assignments")
             .long("no-assignments")
             .help("Disable assignment operator (=)")
+            .global(true)
             .action(clap::ArgAction::SetTrue))
+        .arg(Arg::new("comments")
+            .long("comments")
+            .help("Keep comments in the output JSON, attached to the statements they precede")
+            .action(ArgAction::SetTrue))
         .arg(Arg::new("no-source-mapping")
             .long("no-source-mapping")
             .help("Disable source location information in the output JSON")
             .action(ArgAction::SetTrue))
+        .subcommand(Command::new("format")
+            .about("Print the code with canonical formatting")
+            .arg(Arg::new("code")
+                .help("Code to format")
+                .required(true)
+                .action(ArgAction::Set))
+            .arg(Arg::new("check")
+                .long("check")
+                .help("Print nothing and exit with an error if the code is not already formatted")
+                .action(ArgAction::SetTrue)))
+        .subcommand(Command::new("lint")
+            .about("Report undefined names, unused variables and unreachable code")
+            .arg(Arg::new("code")
+                .help("Code to check")
+                .required(true)
+                .action(ArgAction::Set))
+            .arg(Arg::new("variables")
+                .long("variables")
+                .help("Comma-separated context variables provided by the host")
+                .value_delimiter(',')
+                .action(ArgAction::Append))
+            .arg(Arg::new("functions")
+                .long("functions")
+                .help("Comma-separated remote functions provided by the host")
+                .value_delimiter(',')
+                .action(ArgAction::Append))
+            .arg(Arg::new("manifest")
+                .long("manifest")
+                .help("JSON file with the signatures of the remote functions provided by the host")
+                .action(ArgAction::Set)))
         .disable_help_flag(true)
         .disable_version_flag(true)
         .allow_external_subcommands(true)
         .get_matches();
 
-    let args: Vec<String> = env::args().collect();
+    if let Some(format_matches) = matches.subcommand_matches("format") {
+        format_code(subcommand_code(format_matches), format_matches);
+        return;
+    }
     
+    if let Some(lint_matches) = matches.subcommand_matches("lint") {
+        lint_code(subcommand_code(lint_matches), lint_matches);
+        return;
+    }
+    
+    let args: Vec<String> = env::args().collect();
     let code = extract_code_from_args(&args);
     
     let feature_flags = create_feature_flags_from_cli_args(&matches);
@@ -85,7 +149,13 @@ fn main() {
     
     let include_source_mapping = !matches.get_flag("no-source-mapping");
     
-    match hexput_ast_api::process_code(&code, feature_flags) {
+    let process_result = if matches.get_flag("comments") {
+        hexput_ast_api::process_code_with_comments(&code, feature_flags)
+    } else {
+        hexput_ast_api::process_code(&code, feature_flags)
+    };
+    
+    match process_result {
         Ok(program) => {
             let json_result = if minify {
                 hexput_ast_api::to_json_string(&program, include_source_mapping)
@@ -111,6 +181,91 @@ fn main() {
     }
 }
 
+fn format_code(code: &str, matches: &ArgMatches) {
+    let feature_flags = create_feature_flags_from_cli_args(matches);
+    
+    match hexput_ast_api::parse_code_with_comments(code, feature_flags) {
+        Ok(program) => {
+            let formatted = hexput_ast_api::printer::print_program(&program);
+            
+            if !matches.get_flag("check") {
+                print!("{}", formatted);
+            } else if formatted.trim_end() != code.trim_end() {
+                eprintln!("Code is not formatted");
+                process::exit(1);
+            }
+        }
+        Err(e) => {
+            let error_json = hexput_ast_api::format_error_as_json(&e, matches.get_flag("minify"));
+            eprintln!("{}", error_json);
+            process::exit(1);
+        }
+    }
+}
+
+fn lint_code(code: &str, matches: &ArgMatches) {
+    let feature_flags = create_feature_flags_from_cli_args(matches);
+    
+    let mut host = HostEnvironment::new();
+    if let Some(variables) = matches.get_many::<String>("variables") {
+        host = host.with_variables(variables.cloned());
+    }
+    let mut signatures: Option<Vec<FunctionSignature>> = None;
+    if let Some(path) = matches.get_one::<String>("manifest") {
+        signatures = Some(read_manifest(path));
+    }
+    if let Some(functions) = matches.get_many::<String>("functions") {
+        let signatures = signatures.get_or_insert_with(Vec::new);
+        for name in functions {
+            if !signatures.iter().any(|signature| &signature.name == name) {
+                signatures.push(FunctionSignature::named(name.clone()));
+            }
+        }
+    }
+    if let Some(signatures) = signatures {
+        host = host.with_signatures(signatures);
+    }
+    
+    let diagnostics = hexput_ast_api::lint_code(code, feature_flags, &host);
+    let output = serde_json::json!({ "diagnostics": diagnostics });
+    
+    let json_result = if matches.get_flag("minify") {
+        serde_json::to_string(&output)
+    } else {
+        serde_json::to_string_pretty(&output)
+    };
+    
+    match json_result {
+        Ok(json) => println!("{}", json),
+        Err(e) => {
+            eprintln!("Error serializing diagnostics to JSON: {}", e);
+            process::exit(1);
+        }
+    }
+    
+    if diagnostics.iter().any(|d| d.severity == Severity::Error) {
+        process::exit(1);
+    }
+}
+
+fn read_manifest(path: &str) -> Vec<FunctionSignature> {
+    let manifest = std::fs::read_to_string(path)
+        .map_err(|e| e.to_string())
+        .and_then(|json| serde_json::from_str(&json).map_err(|e| e.to_string()));
+    
+    match manifest {
+        Ok(signatures) => signatures,
+        Err(e) => {
+            eprintln!("Error reading manifest {}: {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn subcommand_code(matches: &ArgMatches) -> &str {
+    matches.get_one::<String>("code").expect("code is a required argument")
+}
+
 fn extract_code_from_args(args: &[String]) -> String {
     if let Some(pos) = args.iter().position(|arg| arg == "::") {
         if pos + 1 < args.len() {
@@ -137,13 +292,14 @@ fn extract_code_from_args(args: &[String]) -> String {
     }
 }
 
-fn create_feature_flags_from_cli_args(args: &clap::ArgMatches) -> FeatureFlags {
+fn create_feature_flags_from_cli_args(args: &ArgMatches) -> FeatureFlags {
     FeatureFlags {
         allow_object_constructions: !args.get_flag("no-object-constructions"),
         allow_array_constructions: !args.get_flag("no-array-constructions"),
         allow_object_navigation: !args.get_flag("no-object-navigation"),
         allow_variable_declaration: !args.get_flag("no-variable-declaration"),
         allow_loops: !args.get_flag("no-loops"),
+        allow_while_loops: !args.get_flag("no-while-loops"),
         allow_object_keys: !args.get_flag("no-object-keys"),
         allow_callbacks: !args.get_flag("no-callbacks"),
         allow_conditionals: !args.get_flag("no-conditionals"),
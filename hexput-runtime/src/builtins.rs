@@ -1,4 +1,5 @@
 use crate::error::RuntimeError;
+use crate::range::RangeValue;
 use hexput_ast_api::ast_structs::SourceLocation;
 use serde_json::{Map, Value};
 use std::future::Future;
@@ -51,20 +52,34 @@ fn execute_string_method(
             }
             Ok(Some(Value::Bool(string.is_empty())))
         }
-        "substring" => {
+        "substring" | "slice" => {
             if args.len() < 1 || args.len() > 2 {
                 return Err(RuntimeError::with_location(
-                    format!("String.substring expects 1-2 arguments, got {}", args.len()),
+                    format!("String.{} expects 1-2 arguments, got {}", method_name, args.len()),
                     location.clone(),
                 ));
             }
 
+            if let Some(range) = RangeValue::from_value(&args[0]) {
+                if args.len() != 1 {
+                    return Err(RuntimeError::with_location(
+                        format!("String.{} expects a single range argument", method_name),
+                        *location,
+                    ));
+                }
+
+                let chars: Vec<char> = string.chars().collect();
+                let indices = get_range_indices(&range, chars.len(), method_name, location)?;
+                let result: String = indices.into_iter().map(|idx| chars[idx]).collect();
+                return Ok(Some(Value::String(result)));
+            }
+
             
-            let start = get_index_arg(&args[0], 0, string.len(), "substring", location)?;
+            let start = get_index_arg(&args[0], 0, string.len(), method_name, location)?;
             
             
             let end = if args.len() > 1 {
-                get_index_arg(&args[1], start, string.len(), "substring", location)?
+                get_index_arg(&args[1], start, string.len(), method_name, location)?
             } else {
                 string.len()
             };
@@ -328,6 +343,19 @@ fn execute_array_method(
                     location.clone(),
                 ));
             }
+
+            if let Some(range) = RangeValue::from_value(&args[0]) {
+                if args.len() != 1 {
+                    return Err(RuntimeError::with_location(
+                        "Array.slice expects a single range argument".to_string(),
+                        *location,
+                    ));
+                }
+
+                let indices = get_range_indices(&range, array.len(), "slice", location)?;
+                let result = indices.into_iter().map(|idx| array[idx].clone()).collect();
+                return Ok(Some(Value::Array(result)));
+            }
             
             
             let start = get_index_arg(&args[0], 0, array.len(), "slice", location)?;
@@ -346,10 +374,10 @@ fn execute_array_method(
                 Ok(Some(Value::Array(vec![])))
             }
         }
-        "map" => {
+        "map" | "parallelMap" => {
             if args.len() != 1 {
                 return Err(RuntimeError::with_location(
-                    format!("Array.map expects 1 argument (callback), got {}", args.len()),
+                    format!("Array.{} expects 1 argument (callback), got {}", method_name, args.len()),
                     location.clone(),
                 ));
             }
@@ -360,20 +388,20 @@ fn execute_array_method(
                     // This will need to be handled asynchronously in the caller
                     return Ok(Some(Value::Object({
                         let mut map = Map::new();
-                        map.insert("__builtin_async_op".to_string(), Value::String("map".to_string()));
+                        map.insert("__builtin_async_op".to_string(), Value::String(method_name.to_string()));
                         map.insert("callback_name".to_string(), Value::String(callback_name));
                         map.insert("array".to_string(), Value::Array(array.to_vec()));
                         map
                     })));
                 } else {
                     return Err(RuntimeError::with_location(
-                        "Callback executor not available for Array.map".to_string(),
+                        format!("Callback executor not available for Array.{}", method_name),
                         location.clone(),
                     ));
                 }
             } else {
                 return Err(RuntimeError::with_location(
-                    "Array.map expects a callback function reference".to_string(),
+                    format!("Array.{} expects a callback function reference", method_name),
                     location.clone(),
                 ));
             }
@@ -889,6 +917,24 @@ fn get_index_arg(
                         ))
                     }
                 }
+            } else if let Some(idx) = n.as_f64().filter(|f| f.fract() == 0.0) {
+                // Number literals are floats, so integral floats count as integers.
+                if idx < 0.0 {
+                    Err(RuntimeError::with_location(
+                        format!("Negative index not allowed in {} method", method_name),
+                        location.clone(),
+                    ))
+                } else {
+                    let idx = idx as usize;
+                    if idx >= min && idx <= max {
+                        Ok(idx)
+                    } else {
+                        Err(RuntimeError::with_location(
+                            format!("Index out of bounds in {} method", method_name),
+                            location.clone(),
+                        ))
+                    }
+                }
             } else {
                 Err(RuntimeError::with_location(
                     format!("{} expects integer arguments", method_name),
@@ -902,3 +948,36 @@ fn get_index_arg(
         )),
     }
 }
+
+/// Indices selected by a range passed to `slice` or `substring`. Every
+/// index must address an existing element.
+fn get_range_indices(
+    range: &RangeValue,
+    len: usize,
+    method_name: &str,
+    location: &SourceLocation
+) -> Result<Vec<usize>, RuntimeError> {
+    range
+        .iter()
+        .map(|idx| {
+            if idx.fract() != 0.0 {
+                Err(RuntimeError::with_location(
+                    format!("{} expects integer arguments", method_name),
+                    *location,
+                ))
+            } else if idx < 0.0 {
+                Err(RuntimeError::with_location(
+                    format!("Negative index not allowed in {} method", method_name),
+                    *location,
+                ))
+            } else if idx as usize >= len {
+                Err(RuntimeError::with_location(
+                    format!("Index out of bounds in {} method", method_name),
+                    *location,
+                ))
+            } else {
+                Ok(idx as usize)
+            }
+        })
+        .collect()
+}
@@ -1,10 +1,13 @@
 use hexput_ast_api::ast_structs::SourceLocation;
+use hexput_ast_api::parser::ParseError;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum RuntimeError {
+    /// Boxed, as the WebSocket error is much larger than the others and
+    /// every `Result` of the runtime carries a `RuntimeError`.
     #[error("WebSocket error: {0}")]
-    WebSocketError(#[from] tokio_tungstenite::tungstenite::Error),
+    WebSocketError(#[source] Box<tokio_tungstenite::tungstenite::Error>),
 
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
@@ -15,6 +18,10 @@ pub enum RuntimeError {
     #[error("AST parsing error: {0}")]
     AstParsingError(String),
 
+    /// The source given to `Interpreter::execute_code` did not parse.
+    #[error("Error parsing AST: {0}")]
+    ParseError(ParseError),
+
     #[error("Invalid request format: {0}")]
     InvalidRequestFormat(String),
 
@@ -51,8 +58,52 @@ pub enum RuntimeError {
     #[error("Channel error: {0}")]
     ChannelError(String),
 
-    #[error("Timeout error: {0}")]
-    TimeoutError(String),
+    #[error("Timeout error{}: {message}", location.map(|l| format!(" at {}", RuntimeError::format_location(&l))).unwrap_or_default())]
+    TimeoutError {
+        message: String,
+        /// The remote function that did not answer in time.
+        function: String,
+        location: Option<SourceLocation>,
+    },
+
+    #[error("Validation error at {}: {message}", RuntimeError::format_location(location))]
+    ValidationError {
+        message: String,
+        location: SourceLocation,
+    },
+
+    #[error("Resource limit exceeded{}: {message}", location.map(|l| format!(" at {}", RuntimeError::format_location(&l))).unwrap_or_default())]
+    ResourceLimitExceeded {
+        message: String,
+        location: Option<SourceLocation>,
+    },
+
+    /// An error raised without a location, given the location of the
+    /// expression it was raised by.
+    #[error("Execution error at {}: {error}", RuntimeError::format_location(location))]
+    WithLocation {
+        error: Box<RuntimeError>,
+        location: SourceLocation,
+    },
+
+    #[error("{error}{}", RuntimeError::format_stack(stack))]
+    WithCallStack {
+        error: Box<RuntimeError>,
+        stack: Vec<CallFrame>,
+    },
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for RuntimeError {
+    fn from(error: tokio_tungstenite::tungstenite::Error) -> Self {
+        RuntimeError::WebSocketError(Box::new(error))
+    }
+}
+
+/// A callback call that was active when an error was raised.
+#[derive(Debug, Clone)]
+pub struct CallFrame {
+    pub name: String,
+    pub location: SourceLocation,
 }
 
 impl RuntimeError {
@@ -66,4 +117,99 @@ impl RuntimeError {
     pub fn with_location(message: String, location: SourceLocation) -> Self {
         RuntimeError::ExecutionErrorWithLocation { message, location }
     }
+
+    /// Records that the error unwound through `frame`. Frames are kept
+    /// innermost first.
+    pub fn with_frame(self, frame: CallFrame) -> Self {
+        match self {
+            RuntimeError::WithCallStack { error, mut stack } => {
+                stack.push(frame);
+                RuntimeError::WithCallStack { error, stack }
+            }
+            error => RuntimeError::WithCallStack {
+                error: Box::new(error),
+                stack: vec![frame],
+            },
+        }
+    }
+
+    pub fn call_stack(&self) -> &[CallFrame] {
+        match self {
+            RuntimeError::WithCallStack { stack, .. } => stack,
+            _ => &[],
+        }
+    }
+
+    fn format_stack(stack: &[CallFrame]) -> String {
+        stack
+            .iter()
+            .map(|frame| format!("\n    at {} ({})", frame.name, RuntimeError::format_location(&frame.location)))
+            .collect()
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RuntimeError::WebSocketError(_) => "WebSocketError",
+            RuntimeError::IoError(_) => "IoError",
+            RuntimeError::SerializationError(_) => "SerializationError",
+            RuntimeError::AstParsingError(_) => "AstParsingError",
+            RuntimeError::ParseError(e) => e.kind(),
+            RuntimeError::InvalidRequestFormat(_) => "InvalidRequestFormat",
+            RuntimeError::MissingField(_) => "MissingField",
+            RuntimeError::ExecutionError(_) => "ExecutionError",
+            RuntimeError::ExecutionErrorWithLocation { .. } => "ExecutionError",
+            RuntimeError::CallbackExecutionError(_) => "CallbackExecutionError",
+            RuntimeError::FunctionCallError(_) => "FunctionCallError",
+            RuntimeError::FunctionNotFoundError(_) => "FunctionNotFoundError",
+            RuntimeError::ConnectionError(_) => "ConnectionError",
+            RuntimeError::MessageParsingError(_) => "MessageParsingError",
+            RuntimeError::TaskExecutionError(_) => "TaskExecutionError",
+            RuntimeError::ChannelError(_) => "ChannelError",
+            RuntimeError::TimeoutError { .. } => "TimeoutError",
+            RuntimeError::ValidationError { .. } => "ValidationError",
+            RuntimeError::ResourceLimitExceeded { .. } => "ResourceLimitExceeded",
+            RuntimeError::WithLocation { error, .. }
+            | RuntimeError::WithCallStack { error, .. } => error.kind(),
+        }
+    }
+
+    /// The error description without its kind prefix or location.
+    pub fn message(&self) -> String {
+        match self {
+            RuntimeError::WebSocketError(e) => e.to_string(),
+            RuntimeError::IoError(e) => e.to_string(),
+            RuntimeError::SerializationError(e) => e.to_string(),
+            RuntimeError::ParseError(e) => e.message(),
+            RuntimeError::AstParsingError(message)
+            | RuntimeError::InvalidRequestFormat(message)
+            | RuntimeError::MissingField(message)
+            | RuntimeError::ExecutionError(message)
+            | RuntimeError::CallbackExecutionError(message)
+            | RuntimeError::FunctionCallError(message)
+            | RuntimeError::FunctionNotFoundError(message)
+            | RuntimeError::ConnectionError(message)
+            | RuntimeError::MessageParsingError(message)
+            | RuntimeError::TaskExecutionError(message)
+            | RuntimeError::ChannelError(message) => message.clone(),
+            RuntimeError::ExecutionErrorWithLocation { message, .. }
+            | RuntimeError::ValidationError { message, .. }
+            | RuntimeError::TimeoutError { message, .. }
+            | RuntimeError::ResourceLimitExceeded { message, .. } => message.clone(),
+            RuntimeError::WithLocation { error, .. }
+            | RuntimeError::WithCallStack { error, .. } => error.message(),
+        }
+    }
+
+    pub fn location(&self) -> Option<SourceLocation> {
+        match self {
+            RuntimeError::ExecutionErrorWithLocation { location, .. }
+            | RuntimeError::ValidationError { location, .. }
+            | RuntimeError::WithLocation { location, .. } => Some(*location),
+            RuntimeError::ResourceLimitExceeded { location, .. }
+            | RuntimeError::TimeoutError { location, .. } => *location,
+            RuntimeError::ParseError(e) => Some(e.location()),
+            RuntimeError::WithCallStack { error, .. } => error.location(),
+            _ => None,
+        }
+    }
 }
@@ -1,89 +1,176 @@
-use crate::error::RuntimeError;
+use crate::error::{CallFrame, RuntimeError};
+use crate::host::{
+    FunctionExistsCache, FunctionHost, RemoteFunctions, PendingFunctionCalls,
+    PendingFunctionValidations, RemoteFunctionHost,
+};
 use crate::messages::{
-    CallbackFunction, ExecutionResult, FunctionCallRequest, FunctionCallResponse,
-    FunctionExistsRequest, FunctionExistsResponse, WebSocketMessage, WebSocketRequest,
-    WebSocketResponse,
+    CallbackFunction, ErrorDetails, ExecutionResult, FunctionCacheScope, FunctionCallResponse,
+    FunctionExistsResponse, ResponseError, WebSocketMessage, WebSocketRequest, WebSocketResponse,
 };
 use crate::builtins;
+use crate::limits::ResourceTracker;
+use crate::range::{self, RangeValue};
 use hexput_ast_api::ast_structs::{Block, Expression, Operator, Program, Property, SourceLocation, Statement, UnaryOperator};
+use hexput_ast_api::analysis::{self, FunctionSignature, HostEnvironment};
+use futures_util::{StreamExt, TryStreamExt};
+use hexput_ast_api::parser::ParseError;
 use serde_json::Value;
 use std::any::type_name_of_val;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use std::time::Instant;
-use tokio::sync::oneshot;
-use tokio::time::{timeout, Duration};
 use tracing::{debug, error, warn};
-use uuid::Uuid;
-
-type PendingFunctionCalls = Arc<Mutex<HashMap<String, oneshot::Sender<FunctionCallResponse>>>>;
-type PendingFunctionValidations =
-    Arc<Mutex<HashMap<String, oneshot::Sender<FunctionExistsResponse>>>>;
 
 const FORBIDDEN_KEY: &str = "secret_data";
 const CALLBACK_REFERENCE_HASH: &str = "__callback_ref_constant";
+/// Evaluates the elements of an array concurrently; see `evaluate_all`.
+const ALL_FUNCTION: &str = "all";
+/// The analysis checks that reject a program before it runs.
+const CALL_CHECKS: [&str; 3] = ["UnknownFunction", "ArgumentCountMismatch", "ArgumentTypeMismatch"];
+
+/// State shared by every statement and expression of a single execution.
+pub(crate) struct ExecutionEnv<'a> {
+    pub(crate) secret_context: Option<&'a Value>,
+    pub(crate) host: &'a dyn FunctionHost,
+    pub(crate) tracker: ResourceTracker,
+}
 
-struct ExecutionContext {
+/// One level of variable and callback bindings.
+///
+/// Scopes are shared rather than copied, so a callback reads and writes the
+/// variables of the scope it was defined in, even while it runs inside a
+/// builtin such as `forEach`.
+#[derive(Default)]
+struct Scope {
     variables: HashMap<String, serde_json::Value>,
     callbacks: HashMap<String, CallbackFunction>,
-    parent: Option<Box<ExecutionContext>>,
+    parent: Option<ScopeRef>,
+}
+
+type ScopeRef = Arc<Mutex<Scope>>;
+
+/// A callback together with the scope it was declared in.
+///
+/// Callbacks live in the scope that declares them, so finding one by name
+/// also finds its defining scope. Nothing else needs to hold on to it, which
+/// keeps scopes free of reference cycles.
+#[derive(Clone)]
+struct Closure {
+    callback: CallbackFunction,
+    scope: ScopeRef,
+}
+
+/// Where a statement or expression runs: its scope, and how many callback
+/// calls deep it is. Branches running at the same time, as in `all` or
+/// `parallelMap`, each have their own context and so their own depth.
+#[derive(Clone)]
+pub(crate) struct ExecutionContext {
+    scope: ScopeRef,
+    call_depth: usize,
 }
 
 impl ExecutionContext {
-    fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Self {
-            variables: HashMap::new(),
-            callbacks: HashMap::new(),
-            parent: None,
+            scope: Arc::new(Mutex::new(Scope::default())),
+            call_depth: 0,
         }
     }
 
-    fn with_parent(parent: &ExecutionContext) -> Self {
+    fn with_parent(parent: &ScopeRef) -> Self {
         Self {
-            variables: HashMap::new(),
-            callbacks: parent.callbacks.clone(),
-            parent: Some(Box::new(parent.clone())),
+            scope: Arc::new(Mutex::new(Scope {
+                parent: Some(parent.clone()),
+                ..Scope::default()
+            })),
+            call_depth: 0,
         }
     }
 
-    fn get_variable(&self, name: &str) -> Option<&serde_json::Value> {
-        if let Some(value) = self.variables.get(name) {
-            return Some(value);
+    /// A context for a block run from this one, in a scope of its own.
+    fn nested(&self) -> Self {
+        Self {
+            call_depth: self.call_depth,
+            ..Self::with_parent(&self.scope)
         }
+    }
 
-        if let Some(parent) = &self.parent {
-            return parent.get_variable(name);
+    /// Walks the scope chain from the innermost scope outwards and returns
+    /// the first scope for which `found` returns a value.
+    fn find_in_scopes<T>(&self, found: impl Fn(&ScopeRef, &Scope) -> Option<T>) -> Option<T> {
+        let mut current = Some(self.scope.clone());
+
+        while let Some(scope_ref) = current {
+            let scope = scope_ref.lock().unwrap();
+            if let Some(value) = found(&scope_ref, &scope) {
+                return Some(value);
+            }
+            current = scope.parent.clone();
         }
 
         None
     }
 
-    fn set_variable(&mut self, name: String, value: serde_json::Value) {
-        self.variables.insert(name, value);
+    fn get_variable(&self, name: &str) -> Option<serde_json::Value> {
+        self.find_in_scopes(|_, scope| scope.variables.get(name).cloned())
     }
 
-    fn get_callback(&self, name: &str) -> Option<&CallbackFunction> {
-        self.callbacks.get(name)
+    /// Binds `name` in the innermost scope, replacing any existing binding.
+    pub(crate) fn set_variable(&mut self, name: String, value: serde_json::Value) {
+        self.scope.lock().unwrap().variables.insert(name, value);
     }
 
-    fn add_callback(&mut self, callback: CallbackFunction) {
-        self.callbacks.insert(callback.name.clone(), callback);
+    /// Declares `name` in the innermost scope. Returns `false`, leaving the
+    /// scope untouched, when the innermost scope already declares it.
+    fn declare_variable(&mut self, name: String, value: serde_json::Value) -> bool {
+        let mut scope = self.scope.lock().unwrap();
+        if scope.variables.contains_key(&name) {
+            return false;
+        }
+        scope.variables.insert(name, value);
+        true
     }
 
-    fn clone(&self) -> Self {
-        Self {
-            variables: self.variables.clone(),
-            callbacks: self.callbacks.clone(),
-            parent: self.parent.as_ref().map(|p| Box::new((**p).clone())),
+    /// Updates `name` in the nearest scope that declares it. Returns `false`
+    /// when no scope does.
+    fn assign_variable(&mut self, name: String, value: serde_json::Value) -> bool {
+        let declaring_scope = self.find_in_scopes(|scope_ref, scope| {
+            scope.variables.contains_key(&name).then(|| scope_ref.clone())
+        });
+
+        match declaring_scope {
+            Some(scope) => {
+                scope.lock().unwrap().variables.insert(name, value);
+                true
+            }
+            None => false,
         }
     }
+
+    fn get_callback(&self, name: &str) -> Option<Closure> {
+        self.find_in_scopes(|scope_ref, scope| {
+            scope.callbacks.get(name).map(|callback| Closure {
+                callback: callback.clone(),
+                scope: scope_ref.clone(),
+            })
+        })
+    }
+
+    fn add_callback(&mut self, callback: CallbackFunction) {
+        self.scope
+            .lock()
+            .unwrap()
+            .callbacks
+            .insert(callback.name.clone(), callback);
+    }
 }
 
 pub async fn handle_message(
     message_data: &str,
     function_calls: PendingFunctionCalls,
     function_validations: PendingFunctionValidations,
-    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    remote_functions: RemoteFunctions,
+    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + 'static,
 ) -> Result<String, RuntimeError> {
     let message: WebSocketMessage = serde_json::from_str(message_data).map_err(|e| {
         RuntimeError::InvalidRequestFormat(format!("Failed to parse message: {}", e))
@@ -91,7 +178,7 @@ pub async fn handle_message(
 
     match message {
         WebSocketMessage::Request(request) => {
-            handle_request(request, function_calls, function_validations, send_message).await
+            handle_request(*request, function_calls, function_validations, remote_functions, send_message).await
         }
         WebSocketMessage::FunctionResponse(response) => {
             handle_function_response_message(response, function_calls).await?;
@@ -112,27 +199,43 @@ pub async fn handle_request(
     request: WebSocketRequest,
     function_calls: PendingFunctionCalls,
     function_validations: PendingFunctionValidations,
-    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    remote_functions: RemoteFunctions,
+    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + 'static,
 ) -> Result<String, RuntimeError> {
     debug!("Received request with ID: {}", request.id);
     debug!("Action: {}", request.action);
 
     match request.action.as_str() {
         "parse" => handle_parse_request(request).await,
+        "diagnose" => handle_diagnose_request(request).await,
+        "lint" => handle_lint_request(request).await,
         "execute" => {
-            handle_execute_request(request, function_calls, function_validations, send_message)
+            handle_execute_request(request, function_calls, function_validations, remote_functions, send_message)
+                .await
+        }
+        "execute_ast" => {
+            handle_execute_ast_request(request, function_calls, function_validations, remote_functions, send_message)
                 .await
         }
+        "register_functions" | "invalidate_functions" => Err(RuntimeError::InvalidRequestFormat(
+            format!("The {} action is handled by the connection", request.action),
+        )),
         "function_response" => Err(RuntimeError::InvalidRequestFormat(
             "Function responses should be processed directly, not through the action field"
                 .to_string(),
         )),
         _ => {
+            let error = RuntimeError::InvalidRequestFormat(format!("Unknown action: {}", request.action));
             let response = WebSocketResponse {
                 id: request.id,
                 success: false,
                 result: None,
-                error: Some(format!("Unknown action: {}", request.action)),
+                error: Some(if request.options.structured_errors {
+                    ResponseError::Details(ErrorDetails::from(&error))
+                } else {
+                    error.message().into()
+                }),
+                cancelled: false,
             };
             Ok(serde_json::to_string(&response)?)
         }
@@ -217,8 +320,13 @@ async fn handle_parse_request(request: WebSocketRequest) -> Result<String, Runti
     
     let process_result = tokio::task::spawn_blocking(move || {
         let feature_flags = options.to_feature_flags();
+        let process_result = if options.include_comments {
+            hexput_ast_api::process_code_with_comments(&code, feature_flags)
+        } else {
+            hexput_ast_api::process_code(&code, feature_flags)
+        };
 
-        match hexput_ast_api::process_code(&code, feature_flags) {
+        match process_result {
             Ok(program) => {
                 let result = if options.minify {
                     hexput_ast_api::to_json_string(&program, options.include_source_mapping)
@@ -229,27 +337,27 @@ async fn handle_parse_request(request: WebSocketRequest) -> Result<String, Runti
                 match result {
                     Ok(json_str) => {
                         match serde_json::from_str::<Value>(&json_str) {
-                            Ok(value) => Ok::<(bool, Option<Value>, Option<String>), RuntimeError>(
+                            Ok(value) => Ok::<(bool, Option<Value>, Option<ResponseError>), RuntimeError>(
                                 (true, Some(value), None),
                             ),
-                            Err(e) => Ok::<(bool, Option<Value>, Option<String>), RuntimeError>((
+                            Err(e) => Ok::<(bool, Option<Value>, Option<ResponseError>), RuntimeError>((
                                 false,
                                 None,
-                                Some(format!("Error deserializing JSON: {}", e)),
+                                Some(format!("Error deserializing JSON: {}", e).into()),
                             )),
                         }
                     }
-                    Err(e) => Ok::<(bool, Option<Value>, Option<String>), RuntimeError>((
+                    Err(e) => Ok::<(bool, Option<Value>, Option<ResponseError>), RuntimeError>((
                         false,
                         None,
-                        Some(format!("Error serializing AST: {}", e)),
+                        Some(format!("Error serializing AST: {}", e).into()),
                     )),
                 }
             }
-            Err(e) => Ok::<(bool, Option<Value>, Option<String>), RuntimeError>((
+            Err(e) => Ok::<(bool, Option<Value>, Option<ResponseError>), RuntimeError>((
                 false,
                 None,
-                Some(format!("Error parsing AST: {}", e)),
+                Some(parse_error_response(&e, options.structured_errors)),
             )),
         }
     })
@@ -266,6 +374,85 @@ async fn handle_parse_request(request: WebSocketRequest) -> Result<String, Runti
         success,
         result,
         error,
+        cancelled: false,
+    };
+
+    Ok(serde_json::to_string(&response)?)
+}
+
+async fn handle_diagnose_request(request: WebSocketRequest) -> Result<String, RuntimeError> {
+    let code = request.code.clone();
+    let options = request.options.clone();
+    let id = request.id.clone();
+
+    let start_time = Instant::now();
+
+    let (program, diagnostics) = tokio::task::spawn_blocking(move || {
+        let feature_flags = options.to_feature_flags();
+        hexput_ast_api::process_code_with_diagnostics(&code, feature_flags)
+    })
+    .await
+    .map_err(|e| RuntimeError::ExecutionError(format!("Task join error: {}", e)))?;
+
+    let elapsed_time = start_time.elapsed();
+    debug!(
+        "Diagnostic parse completed in {:.2?} with {} diagnostics",
+        elapsed_time,
+        diagnostics.len()
+    );
+
+    let program_json = if request.options.minify {
+        hexput_ast_api::to_json_string(&program, request.options.include_source_mapping)?
+    } else {
+        hexput_ast_api::to_json_string_pretty(&program, request.options.include_source_mapping)?
+    };
+
+    let result = serde_json::json!({
+        "program": serde_json::from_str::<Value>(&program_json)?,
+        "diagnostics": diagnostics,
+    });
+
+    let response = WebSocketResponse {
+        id,
+        success: true,
+        result: Some(result),
+        error: None,
+        cancelled: false,
+    };
+
+    Ok(serde_json::to_string(&response)?)
+}
+
+/// Runs the static checks of `hexput_ast_api::analysis` over the code, with
+/// the request context as the host variables.
+async fn handle_lint_request(request: WebSocketRequest) -> Result<String, RuntimeError> {
+    let code = request.code.clone();
+    let feature_flags = request.options.to_feature_flags();
+    let mut host = HostEnvironment::new().with_variables(request.context.keys().cloned());
+    if let Some(functions) = request.functions {
+        host = host.with_signatures(functions);
+    }
+
+    let start_time = Instant::now();
+
+    let diagnostics = tokio::task::spawn_blocking(move || {
+        hexput_ast_api::lint_code(&code, feature_flags, &host)
+    })
+    .await
+    .map_err(|e| RuntimeError::ExecutionError(format!("Task join error: {}", e)))?;
+
+    debug!(
+        "Lint completed in {:.2?} with {} diagnostics",
+        start_time.elapsed(),
+        diagnostics.len()
+    );
+
+    let response = WebSocketResponse {
+        id: request.id,
+        success: true,
+        result: Some(serde_json::json!({ "diagnostics": diagnostics })),
+        error: None,
+        cancelled: false,
     };
 
     Ok(serde_json::to_string(&response)?)
@@ -275,13 +462,11 @@ async fn handle_execute_request(
     request: WebSocketRequest,
     function_calls: PendingFunctionCalls,
     function_validations: PendingFunctionValidations,
-    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    remote_functions: RemoteFunctions,
+    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + 'static,
 ) -> Result<String, RuntimeError> {
     let code = request.code.clone();
     let options = request.options.clone();
-    let id = request.id.clone();
-    let context_variables = request.context.clone();
-    let secret_context = request.secret_context.clone();
 
     let parse_start_time = Instant::now();
     
@@ -299,109 +484,242 @@ async fn handle_execute_request(
         Ok(p) => p,
         Err(e) => {
             let response = WebSocketResponse {
-                id,
+                id: request.id,
+                success: false,
+                result: None,
+                error: Some(parse_error_response(&e, request.options.structured_errors)),
+                cancelled: false,
+            };
+            return Ok(serde_json::to_string(&response)?);
+        }
+    };
+
+    execute_request_program(request, program, function_calls, function_validations, remote_functions, send_message).await
+}
+
+/// Executes the program sent in the `ast` field, as returned by a `parse`
+/// request, so that code run many times is only parsed once.
+async fn handle_execute_ast_request(
+    mut request: WebSocketRequest,
+    function_calls: PendingFunctionCalls,
+    function_validations: PendingFunctionValidations,
+    remote_functions: RemoteFunctions,
+    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + 'static,
+) -> Result<String, RuntimeError> {
+    let program_result = request
+        .ast
+        .take()
+        .ok_or_else(|| RuntimeError::MissingField("ast".to_string()))
+        .and_then(|ast| {
+            serde_json::from_value::<Program>(ast)
+                .map_err(|e| RuntimeError::InvalidRequestFormat(format!("Invalid AST: {}", e)))
+        });
+
+    let program = match program_result {
+        Ok(p) => p,
+        Err(e) => {
+            let response = WebSocketResponse {
+                id: request.id,
+                success: false,
+                result: None,
+                error: Some(runtime_error_response(&e, request.options.structured_errors)),
+                cancelled: false,
+            };
+            return Ok(serde_json::to_string(&response)?);
+        }
+    };
+
+    // The program did not go through the parser with this request's flags,
+    // so reject the features they disable here instead.
+    if let Err(e) = request.options.to_feature_flags().check_program(&program) {
+        let response = WebSocketResponse {
+            id: request.id,
+            success: false,
+            result: None,
+            error: Some(parse_error_response(&e, request.options.structured_errors)),
+            cancelled: false,
+        };
+        return Ok(serde_json::to_string(&response)?);
+    }
+
+    execute_request_program(request, program, function_calls, function_validations, remote_functions, send_message).await
+}
+
+async fn execute_request_program(
+    request: WebSocketRequest,
+    program: Program,
+    function_calls: PendingFunctionCalls,
+    function_validations: PendingFunctionValidations,
+    remote_functions: RemoteFunctions,
+    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + 'static,
+) -> Result<String, RuntimeError> {
+    if let Some(functions) = &request.functions {
+        if let Err(e) = validate_calls(&program, functions) {
+            let response = WebSocketResponse {
+                id: request.id,
                 success: false,
                 result: None,
-                error: Some(format!("Error parsing AST: {}", e)),
+                error: Some(runtime_error_response(&e, request.options.structured_errors)),
+                cancelled: false,
             };
             return Ok(serde_json::to_string(&response)?);
         }
+    }
+
+    let mut host = RemoteFunctionHost::new(function_calls, function_validations, Arc::new(send_message));
+    if let Some(functions) = &request.functions {
+        host = host.with_manifest(functions);
+    }
+    if remote_functions.skip_existence_check {
+        host = host.skip_existence_check();
+    }
+    if remote_functions.batch_calls {
+        host = host.with_batching();
+    }
+    host = host.with_timeouts(remote_functions.timeouts.with_overrides(&request.timeouts));
+    match request.options.function_cache {
+        FunctionCacheScope::Connection => host = host.with_exists_cache(remote_functions.exists_cache),
+        FunctionCacheScope::Request => host = host.with_exists_cache(FunctionExistsCache::default()),
+        FunctionCacheScope::Disabled => {}
+    }
+    let env = ExecutionEnv {
+        secret_context: request.secret_context.as_ref(),
+        host: &host,
+        tracker: ResourceTracker::new(request.limits),
     };
 
     let exec_start_time = Instant::now();
     
-    let execution_result =
-        execute_program(program, context_variables, secret_context, function_calls, function_validations, send_message).await;
+    let execution_result = execute_program(program, request.context, &env, request.options.structured_errors).await;
     
     let exec_elapsed = exec_start_time.elapsed();
     debug!("Program execution completed in {:.2?}", exec_elapsed);
 
-    let error_message = match &execution_result.error {
-        Some(error_text) => {
-            if error_text.contains("line") && error_text.contains("column") {
-                Some(error_text.clone())
-            } else {
-                Some(error_text.clone())
-            }
-        }
-        _ => None,
-    };
-
     let response = WebSocketResponse {
-        id,
+        id: request.id,
         success: execution_result.error.is_none(),
         result: Some(execution_result.value),
-        error: error_message,
+        error: execution_result.error,
+        cancelled: false,
     };
 
     Ok(serde_json::to_string(&response)?)
 }
 
+/// Checks the calls of `program` against the functions the client declared,
+/// so that a call to an unknown function or with the wrong arguments fails
+/// before anything runs.
+fn validate_calls(program: &Program, functions: &[FunctionSignature]) -> Result<(), RuntimeError> {
+    let host = HostEnvironment::new().with_signatures(functions.iter().cloned());
+
+    match analysis::analyze(program, &host)
+        .into_iter()
+        .find(|diagnostic| CALL_CHECKS.contains(&diagnostic.code.as_str()))
+    {
+        Some(diagnostic) => Err(RuntimeError::ValidationError {
+            message: diagnostic.message,
+            location: diagnostic.location,
+        }),
+        None => Ok(()),
+    }
+}
+
 async fn execute_program(
     program: Program,
     context_variables: serde_json::Map<String, serde_json::Value>,
-    secret_context: Option<serde_json::Value>,
-    function_calls: PendingFunctionCalls,
-    function_validations: PendingFunctionValidations,
-    send_message: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
+    structured_errors: bool,
 ) -> ExecutionResult {
-    let mut context = ExecutionContext::new();
+    match run_program(program.statements, context_variables, env).await {
+        Ok(value) => ExecutionResult { value, error: None },
+        Err(e) => ExecutionResult {
+            value: serde_json::Value::Null,
+            error: Some(runtime_error_response(&e, structured_errors)),
+        },
+    }
+}
+
+/// Formats an execution error for the response, as the full message or as a
+/// structured object when the client asked for structured errors.
+pub(crate) fn runtime_error_response(error: &RuntimeError, structured_errors: bool) -> ResponseError {
+    if structured_errors {
+        ResponseError::Details(ErrorDetails::from(error))
+    } else {
+        error.to_string().into()
+    }
+}
+
+fn parse_error_response(error: &ParseError, structured_errors: bool) -> ResponseError {
+    if structured_errors {
+        ResponseError::Details(ErrorDetails::from(error))
+    } else {
+        format!("Error parsing AST: {}", error).into()
+    }
+}
+
+pub(crate) async fn run_program(
+    statements: Vec<Statement>,
+    context_variables: serde_json::Map<String, serde_json::Value>,
+    env: &ExecutionEnv<'_>,
+) -> Result<serde_json::Value, RuntimeError> {
+    let program_future = run_statements(statements, context_variables, env);
+
+    // The tracker only notices the deadline between statements, so the whole
+    // run is also bounded while it waits on remote function calls.
+    match env.tracker.limits().timeout() {
+        Some(duration) => tokio::time::timeout(duration, program_future)
+            .await
+            .map_err(|_| env.tracker.timeout_error())?,
+        None => program_future.await,
+    }
+}
+
+async fn run_statements(
+    statements: Vec<Statement>,
+    context_variables: serde_json::Map<String, serde_json::Value>,
+    env: &ExecutionEnv<'_>,
+) -> Result<serde_json::Value, RuntimeError> {
+    let mut host_context = ExecutionContext::new();
     
     for (name, value) in context_variables {
-        context.set_variable(name, value);
+        host_context.set_variable(name, value);
     }
 
-    for statement in program.statements {
-        match Box::pin(execute_statement(
-            statement,
-            &mut context,
-            secret_context.as_ref(),
-            function_calls.clone(),
-            function_validations.clone(),
-            &send_message,
-        ))
-        .await
-        {
-            Ok(Some(value)) => {
-                if let Some(control_type) = get_control_flow_type(&value) {
-                    if control_type == CONTROL_RETURN {
-                        let return_value = extract_return_value(value);
-                        debug!("Returning value from program due to return statement");
-                        return ExecutionResult {
-                            value: return_value,
-                            error: None,
-                        };
-                    }
+    // Scripts declare their variables in a scope of their own, so `vl` may
+    // shadow a context variable.
+    let mut context = ExecutionContext::with_parent(&host_context.scope);
 
-                    debug!(
-                        "Ignoring control flow signal at program level: {}",
-                        control_type
-                    );
-                } else {
-                    debug!("Unexpected value return at program level");
-                    return ExecutionResult { value, error: None };
-                }
-            }
-            Ok(None) => {}
-            Err(e) => {
-                return ExecutionResult {
-                    value: serde_json::Value::Null,
-                    error: Some(e.to_string()),
+    for statement in statements {
+        if let Some(value) = Box::pin(execute_statement(statement, &mut context, env)).await? {
+            if let Some(control_type) = get_control_flow_type(&value) {
+                if control_type == CONTROL_RETURN {
+                    debug!("Returning value from program due to return statement");
+                    return Ok(extract_return_value(value));
                 }
+
+                debug!(
+                    "Ignoring control flow signal at program level: {}",
+                    control_type
+                );
+            } else {
+                debug!("Unexpected value return at program level");
+                return Ok(value);
             }
         }
     }
 
-    ExecutionResult {
-        value: serde_json::Value::Null,
-        error: None,
-    }
+    Ok(serde_json::Value::Null)
 }
 
 const CONTROL_TYPE_KEY: &str = "__control_type";
 const CONTROL_CONTINUE: &str = "continue";
 const CONTROL_END: &str = "end";
 const CONTROL_RETURN: &str = "return";
+/// Built-in methods given a range argument as a range, to select indices,
+/// rather than as the array of its numbers.
+const RANGE_METHODS: [&str; 2] = ["slice", "substring"];
+/// Number of loop iterations between yields to the scheduler.
+const LOOP_YIELD_INTERVAL: u64 = 256;
 
 fn get_control_flow_type(value: &serde_json::Value) -> Option<&str> {
     if let serde_json::Value::Object(map) = value {
@@ -426,49 +744,42 @@ fn extract_return_value(value: serde_json::Value) -> serde_json::Value {
 async fn execute_statement(
     statement: Statement,
     context: &mut ExecutionContext,
-    secret_context: Option<&serde_json::Value>,
-    function_calls: PendingFunctionCalls,
-    function_validations: PendingFunctionValidations,
-    send_message: &impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
 ) -> Result<Option<serde_json::Value>, RuntimeError> {
-    let location = match &statement {
-        Statement::VariableDeclaration { location, .. } => location.clone(),
-        Statement::ExpressionStatement { location, .. } => location.clone(),
-        Statement::IfStatement { location, .. } => location.clone(),
-        Statement::Block { location, .. } => location.clone(),
-        Statement::LoopStatement { location, .. } => location.clone(),
-        Statement::CallbackDeclaration { location, .. } => location.clone(),
-        Statement::ReturnStatement { location, .. } => location.clone(),
-        Statement::EndStatement { location } => location.clone(),
-        Statement::ContinueStatement { location } => location.clone(),
-    };
+    let location = statement.location();
+
+    if let Err(e) = env.tracker.step() {
+        return Err(add_location_if_needed(e, &location));
+    }
 
     match statement {
         Statement::VariableDeclaration { name, value, .. } => {
             let value_result = match Box::pin(evaluate_expression(
                 value,
                 context,
-                secret_context,
-                function_calls,
-                function_validations,
-                send_message,
+                env,
             ))
             .await
             {
                 Ok(val) => val,
                 Err(e) => return Err(add_location_if_needed(e, &location)),
             };
-            context.set_variable(name, value_result);
+            if let Err(e) = env.tracker.check_value(&value_result) {
+                return Err(add_location_if_needed(e, &location));
+            }
+            if !context.declare_variable(name.clone(), value_result) {
+                return Err(RuntimeError::with_location(
+                    format!("Variable already declared in this scope: {}", name),
+                    location,
+                ));
+            }
             Ok(None)
         }
         Statement::ExpressionStatement { expression, .. } => {
             match Box::pin(evaluate_expression(
                 expression,
                 context,
-                secret_context,
-                function_calls,
-                function_validations,
-                send_message,
+                env,
             ))
             .await
             {
@@ -485,10 +796,7 @@ async fn execute_statement(
             let condition_value = match Box::pin(evaluate_expression(
                 condition,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -509,10 +817,7 @@ async fn execute_statement(
                 match execute_block(
                     body,
                     context,
-                    secret_context,
-                    function_calls,
-                    function_validations,
-                    send_message,
+                    env,
                 )
                 .await
                 {
@@ -523,10 +828,7 @@ async fn execute_statement(
                 match execute_block(
                     else_block,
                     context,
-                    secret_context,
-                    function_calls,
-                    function_validations,
-                    send_message,
+                    env,
                 )
                 .await
                 {
@@ -541,26 +843,67 @@ async fn execute_statement(
             execute_block(
                 block,
                 context,
-                secret_context,
-                function_calls,
-                function_validations,
-                send_message,
+                env,
             )
             .await
         }
         Statement::LoopStatement {
+            key_variable,
             variable,
             iterable,
             body,
             ..
         } => {
+            // A range written as the iterable is expanded one number at a
+            // time instead of being built as an array.
+            if let Expression::RangeExpression { start, end, step, inclusive, location: range_location } = iterable {
+                let range = match evaluate_range(*start, *end, step, inclusive, range_location, context, env).await {
+                    Ok(range) => range,
+                    Err(e) => return Err(add_location_if_needed(e, &location)),
+                };
+
+                for (iteration, number) in (1..).zip(range.iter()) {
+                    if let Err(e) = env
+                        .tracker
+                        .step()
+                        .and_then(|_| env.tracker.check_loop_iteration(iteration))
+                    {
+                        return Err(add_location_if_needed(e, &location));
+                    }
+
+                    if iteration.is_multiple_of(LOOP_YIELD_INTERVAL) {
+                        tokio::task::yield_now().await;
+                    }
+                    let mut iteration_context = context.nested();
+                    if let Some(key_variable) = &key_variable {
+                        iteration_context.set_variable(key_variable.clone(), range::number_value((iteration - 1) as f64));
+                    }
+                    iteration_context.set_variable(variable.clone(), range::number_value(number));
+
+                    match execute_block_in(
+                        body.clone(),
+                        &mut iteration_context,
+                        env,
+                    )
+                    .await
+                    {
+                        Ok(Some(value)) => match get_control_flow_type(&value) {
+                            Some(CONTROL_CONTINUE) => continue,
+                            Some(CONTROL_END) => break,
+                            _ => return Ok(Some(value)),
+                        },
+                        Ok(None) => {}
+                        Err(e) => return Err(add_location_if_needed(e, &location)),
+                    }
+                }
+
+                return Ok(None);
+            }
+
             let iterable_value = match Box::pin(evaluate_expression(
                 iterable,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -568,84 +911,34 @@ async fn execute_statement(
                 Err(e) => return Err(add_location_if_needed(e, &location)),
             };
 
-            match iterable_value {
-                serde_json::Value::Array(items) => {
-                    for item in items {
-                        context.set_variable(variable.clone(), item);
-
-                        match execute_block(
-                            body.clone(),
-                            context,
-                            secret_context,
-                            function_calls.clone(),
-                            function_validations.clone(),
-                            send_message,
-                        )
-                        .await
-                        {
-                            Ok(result) => {
-                                if let Some(value) = result {
-                                    if let Some(control_type) = get_control_flow_type(&value) {
-                                        match control_type {
-                                            CONTROL_CONTINUE => {
-                                                continue;
-                                            }
-                                            CONTROL_END => {
-                                                break;
-                                            }
-                                            CONTROL_RETURN => {
-                                                return Ok(Some(value));
-                                            }
-                                            _ => {
-                                                return Ok(Some(value));
-                                            }
-                                        }
-                                    } else {
-                                        return Ok(Some(value));
-                                    }
-                                }
-                            }
-                            Err(e) => return Err(add_location_if_needed(e, &location)),
-                        }
-                    }
-                }
-                serde_json::Value::String(s) => {
-                    for ch in s.chars() {
-                        let char_value = serde_json::Value::String(ch.to_string());
-                        context.set_variable(variable.clone(), char_value);
-
-                        let result = execute_block(
-                            body.clone(),
-                            context,
-                            secret_context,
-                            function_calls.clone(),
-                            function_validations.clone(),
-                            send_message,
-                        )
-                        .await?;
-
-                        if let Some(value) = result {
-                            if let Some(control_type) = get_control_flow_type(&value) {
-                                match control_type {
-                                    CONTROL_CONTINUE => {
-                                        continue;
-                                    }
-                                    CONTROL_END => {
-                                        break;
-                                    }
-                                    CONTROL_RETURN => {
-                                        return Ok(Some(value));
-                                    }
-                                    _ => {
-                                        return Ok(Some(value));
-                                    }
-                                }
-                            } else {
-                                return Ok(Some(value));
-                            }
-                        }
-                    }
-                }
+            // Each entry is a key or index and the value bound to it. A
+            // single loop variable gets the value, except for objects where
+            // it gets the key, matching `keysof`.
+            let (entries, binds_keys): (Vec<(serde_json::Value, serde_json::Value)>, bool) = match iterable_value {
+                serde_json::Value::Array(items) => (
+                    items
+                        .into_iter()
+                        .enumerate()
+                        .map(|(index, item)| (range::number_value(index as f64), item))
+                        .collect(),
+                    false,
+                ),
+                serde_json::Value::String(s) => (
+                    s.chars()
+                        .enumerate()
+                        .map(|(index, ch)| {
+                            (range::number_value(index as f64), serde_json::Value::String(ch.to_string()))
+                        })
+                        .collect(),
+                    false,
+                ),
+                serde_json::Value::Object(map) => (
+                    map.into_iter()
+                        .filter(|(key, _)| key != FORBIDDEN_KEY)
+                        .map(|(key, item)| (serde_json::Value::String(key), item))
+                        .collect(),
+                    true,
+                ),
                 _ => {
                     return Err(RuntimeError::with_location(
                         format!(
@@ -654,13 +947,114 @@ async fn execute_statement(
                                 serde_json::Value::Null => "null",
                                 serde_json::Value::Bool(_) => "boolean",
                                 serde_json::Value::Number(_) => "number",
-                                serde_json::Value::Object(_) => "object",
                                 _ => "unknown",
                             }
                         ),
                         location,
                     ))
                 }
+            };
+
+            for (iteration, (key, item)) in (1u64..).zip(entries) {
+                if let Err(e) = env.tracker.step() {
+                    return Err(add_location_if_needed(e, &location));
+                }
+
+                if iteration.is_multiple_of(LOOP_YIELD_INTERVAL) {
+                    tokio::task::yield_now().await;
+                }
+
+                let mut iteration_context = context.nested();
+                match &key_variable {
+                    Some(key_variable) => {
+                        iteration_context.set_variable(key_variable.clone(), key);
+                        iteration_context.set_variable(variable.clone(), item);
+                    }
+                    None if binds_keys => iteration_context.set_variable(variable.clone(), key),
+                    None => iteration_context.set_variable(variable.clone(), item),
+                }
+
+                match execute_block_in(
+                    body.clone(),
+                    &mut iteration_context,
+                    env,
+                )
+                .await
+                {
+                    Ok(Some(value)) => match get_control_flow_type(&value) {
+                        Some(CONTROL_CONTINUE) => continue,
+                        Some(CONTROL_END) => break,
+                        _ => return Ok(Some(value)),
+                    },
+                    Ok(None) => {}
+                    Err(e) => return Err(add_location_if_needed(e, &location)),
+                }
+            }
+
+            Ok(None)
+        }
+        Statement::WhileLoopStatement {
+            condition,
+            body,
+            ..
+        } => {
+            let mut iteration: u64 = 0;
+
+            loop {
+                iteration += 1;
+                if let Err(e) = env
+                    .tracker
+                    .step()
+                    .and_then(|_| env.tracker.check_loop_iteration(iteration))
+                {
+                    return Err(add_location_if_needed(e, &location));
+                }
+
+                // Loop bodies that never await would otherwise keep the
+                // worker busy and the task could not be cancelled.
+                if iteration.is_multiple_of(LOOP_YIELD_INTERVAL) {
+                    tokio::task::yield_now().await;
+                }
+
+                let condition_value = match Box::pin(evaluate_expression(
+                    condition.clone(),
+                    context,
+                    env,
+                ))
+                .await
+                {
+                    Ok(val) => val,
+                    Err(e) => return Err(add_location_if_needed(e, &location)),
+                };
+
+                let is_truthy = match condition_value {
+                    serde_json::Value::Bool(b) => b,
+                    serde_json::Value::Number(n) => n.as_f64().unwrap_or(0.0) != 0.0,
+                    serde_json::Value::String(s) => !s.is_empty(),
+                    serde_json::Value::Array(a) => !a.is_empty(),
+                    serde_json::Value::Object(o) => !o.is_empty(),
+                    serde_json::Value::Null => false,
+                };
+
+                if !is_truthy {
+                    break;
+                }
+
+                match execute_block(
+                    body.clone(),
+                    context,
+                    env,
+                )
+                .await
+                {
+                    Ok(Some(value)) => match get_control_flow_type(&value) {
+                        Some(CONTROL_CONTINUE) => continue,
+                        Some(CONTROL_END) => break,
+                        _ => return Ok(Some(value)),
+                    },
+                    Ok(None) => {}
+                    Err(e) => return Err(add_location_if_needed(e, &location)),
+                }
             }
 
             Ok(None)
@@ -681,10 +1075,7 @@ async fn execute_statement(
             let return_value = Box::pin(evaluate_expression(
                 value,
                 context,
-                secret_context,
-                function_calls,
-                function_validations,
-                send_message,
+                env,
             ))
             .await?;
             debug!("Processing return statement with value: {:?}", return_value);
@@ -720,28 +1111,68 @@ fn add_location_if_needed(
     location: &SourceLocation,
 ) -> RuntimeError {
     match error {
-        RuntimeError::ExecutionErrorWithLocation { .. } => error,
-
-        _ => RuntimeError::with_location(error.to_string(), location.clone()),
+        RuntimeError::ExecutionError(message) => RuntimeError::with_location(message, *location),
+        RuntimeError::ResourceLimitExceeded {
+            message,
+            location: None,
+        } => RuntimeError::ResourceLimitExceeded {
+            message,
+            location: Some(*location),
+        },
+        RuntimeError::TimeoutError {
+            message,
+            function,
+            location: None,
+        } => RuntimeError::TimeoutError {
+            message,
+            function,
+            location: Some(*location),
+        },
+        // Errors keep the location they were raised at, or, when unwinding
+        // from a callback, the call stack that leads there.
+        RuntimeError::WithCallStack { .. } => error,
+        _ if error.location().is_some() => error,
+        _ => RuntimeError::WithLocation {
+            error: Box::new(error),
+            location: *location,
+        },
     }
 }
 
+async fn call_host_function(
+    name: &str,
+    arguments: Vec<serde_json::Value>,
+    location: SourceLocation,
+    env: &ExecutionEnv<'_>,
+) -> Result<serde_json::Value, RuntimeError> {
+    env.host
+        .call_function(name, arguments, env.secret_context)
+        .await
+        .map_err(|e| add_location_if_needed(e, &location))
+}
+
+/// Runs a block in a new scope nested in `context`.
 async fn execute_block(
     block: Block,
     context: &mut ExecutionContext,
-    secret_context: Option<&serde_json::Value>,
-    function_calls: PendingFunctionCalls,
-    function_validations: PendingFunctionValidations,
-    send_message: &impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
+) -> Result<Option<serde_json::Value>, RuntimeError> {
+    let mut block_context = context.nested();
+    execute_block_in(block, &mut block_context, env).await
+}
+
+/// Runs a block directly in `context`, for callers that have already created
+/// the block's scope to bind loop variables or parameters in it.
+async fn execute_block_in(
+    block: Block,
+    context: &mut ExecutionContext,
+    env: &ExecutionEnv<'_>,
 ) -> Result<Option<serde_json::Value>, RuntimeError> {
     for statement in block.statements {
         let statement_future = Box::pin(execute_statement(
             statement,
             context,
-            secret_context,
-            function_calls.clone(),
-            function_validations.clone(),
-            send_message,
+            env,
         ));
 
         let result = statement_future.await?;
@@ -758,10 +1189,7 @@ async fn execute_block(
 async fn extract_property_path(
     expression: &Expression,
     context: &mut ExecutionContext,
-    secret_context: Option<&serde_json::Value>,
-    function_calls: &PendingFunctionCalls,
-    function_validations: &PendingFunctionValidations,
-    send_message: &impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
 ) -> Result<Vec<String>, RuntimeError> {
 
     let mut path = Vec::new();
@@ -800,10 +1228,7 @@ async fn extract_property_path(
                     let prop_value = Box::pin(evaluate_expression(
                         (**prop_expr).clone(),
                         context,
-                        secret_context,
-                        function_calls.clone(),
-                        function_validations.clone(),
-                        send_message,
+                        env,
                     ))
                     .await?;
 
@@ -846,11 +1271,26 @@ async fn extract_property_path(
     Ok(path)
 }
 
+/// Makes `index` a valid index of `arr`, padding it with nulls. The padding
+/// is checked against the value size limit before it is added.
+fn pad_array(
+    arr: &mut Vec<serde_json::Value>,
+    index: usize,
+    tracker: &ResourceTracker,
+) -> Result<(), RuntimeError> {
+    if index >= arr.len() {
+        tracker.check_array_padding(index - arr.len())?;
+        arr.resize(index + 1, serde_json::Value::Null);
+    }
+    Ok(())
+}
+
 fn update_nested_object(
     object: &mut serde_json::Value,
     path: &[String],
     path_index: usize,
     value: serde_json::Value,
+    tracker: &ResourceTracker,
 ) -> Result<(), RuntimeError> {
     if path_index >= path.len() {
         return Err(RuntimeError::ExecutionError(
@@ -878,10 +1318,7 @@ fn update_nested_object(
             serde_json::Value::Array(arr) if is_array_index => {
                 let index = current_prop.parse::<usize>().unwrap();
 
-                while arr.len() <= index {
-                    arr.push(serde_json::Value::Null);
-                }
-
+                pad_array(arr, index, tracker)?;
                 arr[index] = value;
                 Ok(())
             }
@@ -909,14 +1346,12 @@ fn update_nested_object(
                         .or_insert_with(|| serde_json::Value::Object(serde_json::Map::new()))
                 };
 
-                update_nested_object(next_obj, path, path_index + 1, value)
+                update_nested_object(next_obj, path, path_index + 1, value, tracker)
             }
             serde_json::Value::Array(arr) if is_array_index => {
                 let index = current_prop.parse::<usize>().unwrap();
 
-                while arr.len() <= index {
-                    arr.push(serde_json::Value::Null);
-                }
+                pad_array(arr, index, tracker)?;
 
                 let next_is_numeric =
                     path_index + 1 < path.len() && path[path_index + 1].parse::<usize>().is_ok();
@@ -929,7 +1364,7 @@ fn update_nested_object(
                     }
                 }
 
-                update_nested_object(&mut arr[index], path, path_index + 1, value)
+                update_nested_object(&mut arr[index], path, path_index + 1, value, tracker)
             }
             _ => Err(RuntimeError::ExecutionError(format!(
                 "Cannot access index or property '{}' on non-object/non-array value",
@@ -942,30 +1377,10 @@ fn update_nested_object(
 async fn evaluate_expression(
     expression: Expression,
     context: &mut ExecutionContext,
-    secret_context: Option<&serde_json::Value>,
-    function_calls: PendingFunctionCalls,
-    function_validations: PendingFunctionValidations,
-    send_message: &impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
 ) -> Result<serde_json::Value, RuntimeError> {
 
-    let location = match &expression {
-        Expression::StringLiteral { location, .. } => location.clone(),
-        Expression::NumberLiteral { location, .. } => location.clone(),
-        Expression::Identifier { location, .. } => location.clone(),
-        Expression::BinaryExpression { location, .. } => location.clone(),
-        Expression::AssignmentExpression { location, .. } => location.clone(),
-        Expression::MemberAssignmentExpression { location, .. } => location.clone(),
-        Expression::CallExpression { location, .. } => location.clone(),
-        Expression::MemberCallExpression { location, .. } => location.clone(),
-        Expression::InlineCallbackExpression { location, .. } => location.clone(),
-        Expression::ArrayExpression { location, .. } => location.clone(),
-        Expression::ObjectExpression { location, .. } => location.clone(),
-        Expression::MemberExpression { location, .. } => location.clone(),
-        Expression::KeysOfExpression { location, .. } => location.clone(),
-        Expression::BooleanLiteral { location, .. } => location.clone(),
-        Expression::UnaryExpression { location, .. } => location.clone(),
-        Expression::NullLiteral { location } => location.clone(),
-    };
+    let location = expression.location();
 
     match expression {
         Expression::StringLiteral { value, .. } => Ok(serde_json::Value::String(value)),
@@ -983,7 +1398,7 @@ async fn evaluate_expression(
                 }))
             } else {
                 // Then check for regular variables
-                context.get_variable(&name).cloned().ok_or_else(|| {
+                context.get_variable(&name).ok_or_else(|| {
                     RuntimeError::with_location(format!("Undefined variable: {}", name), location)
                 })
             }
@@ -991,90 +1406,38 @@ async fn evaluate_expression(
         Expression::CallExpression {
             callee, arguments, ..
         } => {
-            if let Some(callback) = context.get_callback(&callee).cloned() {
+            if let Some(callback) = context.get_callback(&callee) {
                 debug!("Executing local callback: {}", callee);
                 match execute_callback(
                     callback,
                     arguments,
                     context,
-                    secret_context,
-                    function_calls,
-                    function_validations,
-                    send_message,
+                    env,
+                    callback_frame_name(&callee, None),
+                    location,
                 )
                 .await
                 {
                     Ok(val) => Ok(val),
                     Err(e) => Err(add_location_if_needed(e, &location)),
                 }
+            } else if callee == ALL_FUNCTION {
+                evaluate_all(arguments, context, env, location).await
             } else {
-                debug!("Checking if remote function exists: {}", callee);
-
-                let check_id = Uuid::new_v4().to_string();
-
-                let (tx, rx) = oneshot::channel::<FunctionExistsResponse>();
-
-                {
-                    let mut validations = function_validations.lock().unwrap();
-                    validations.insert(check_id.clone(), tx);
-                }
-
-                let exists_request = FunctionExistsRequest {
-                    id: check_id.clone(),
-                    action: "is_function_exists".to_string(),
-                    function_name: callee.clone(),
-                };
-
-                let request_json = match serde_json::to_string(&exists_request) {
-                    Ok(json) => json,
-                    Err(e) => {
-                        return Err(RuntimeError::with_location(
-                            format!("Serialization error: {}", e),
-                            location,
-                        ))
-                    }
-                };
-
-                match send_message(request_json).await {
-                    Ok(_) => {}
+                let function_exists = match env.host.function_exists(&callee).await {
+                    Ok(exists) => exists,
                     Err(e) => return Err(add_location_if_needed(e, &location)),
-                }
-
-                let function_exists = match timeout(Duration::from_secs(3), rx).await {
-                    Ok(response_result) => match response_result {
-                        Ok(response) => response.exists,
-                        Err(_) => {
-                            debug!(
-                                "Function exists check response channel closed for '{}'",
-                                callee
-                            );
-                            false
-                        }
-                    },
-                    Err(_) => {
-                        {
-                            let mut validations = function_validations.lock().unwrap();
-                            validations.remove(&check_id);
-                        }
-                        debug!("Function exists check timed out for '{}'", callee);
-                        false
-                    }
                 };
 
                 if function_exists {
                     debug!("Remote function '{}' exists, proceeding with call", callee);
 
-                    let call_id = Uuid::new_v4().to_string();
-
                     let mut evaluated_args = Vec::new();
                     for arg in arguments {
                         match Box::pin(evaluate_expression(
                             arg,
                             context,
-                            secret_context,
-                            function_calls.clone(),
-                            function_validations.clone(),
-                            send_message,
+                            env,
                         ))
                         .await
                         {
@@ -1083,71 +1446,11 @@ async fn evaluate_expression(
                         }
                     }
 
-                    let (tx, rx) = oneshot::channel::<FunctionCallResponse>();
-
-                    {
-                        let mut calls = function_calls.lock().unwrap();
-                        calls.insert(call_id.clone(), tx);
-                    }
-
-                    let request = FunctionCallRequest {
-                        id: call_id.clone(),
-                        function_name: callee.clone(),
-                        arguments: evaluated_args,
-                        secret_context: secret_context.cloned(),
-                    };
-
-                    let request_json = match serde_json::to_string(&request) {
-                        Ok(json) => json,
-                        Err(e) => {
-                            return Err(RuntimeError::with_location(
-                                format!("Serialization error: {}", e),
-                                location,
-                            ))
-                        }
-                    };
-
-                    match send_message(request_json).await {
-                        Ok(_) => {}
-                        Err(e) => return Err(add_location_if_needed(e, &location)),
-                    }
-
-                    match timeout(Duration::from_secs(600), rx).await {
-                        Ok(response_result) => match response_result {
-                            Ok(response) => {
-                                if let Some(err) = response.error {
-                                    Err(RuntimeError::with_location(
-                                        format!("Remote function error: {}", err),
-                                        location,
-                                    ))
-                                } else {
-                                    Ok(response.result)
-                                }
-                            },
-                            Err(_) => Err(RuntimeError::with_location(
-                                "Function call response channel closed".to_string(),
-                                location,
-                            )),
-                        },
-                        Err(_) => {
-                            {
-                                let mut calls = function_calls.lock().unwrap();
-                                calls.remove(&call_id);
-                            }
-
-                            warn!("Function call '{}' timed out after 60 seconds", callee);
-                            Err(RuntimeError::with_location(
-                                format!("Function call '{}' timed out", callee),
-                                location,
-                            ))
-                        }
-                    }
+                    call_host_function(&callee, evaluated_args, location, env).await
                 } else {
                     warn!("Remote function '{}' does not exist", callee);
-                    Err(RuntimeError::FunctionNotFoundError(format!(
-                        "Function '{}' not found",
-                        callee
-                    )))
+                    let error = RuntimeError::FunctionNotFoundError(format!("Function '{}' not found", callee));
+                    Err(add_location_if_needed(error, &location))
                 }
             }
         }
@@ -1160,10 +1463,7 @@ async fn evaluate_expression(
             let left_value = match Box::pin(evaluate_expression(
                 *left,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -1189,10 +1489,7 @@ async fn evaluate_expression(
                     let right_value = match Box::pin(evaluate_expression(
                         *right,
                         context,
-                        secret_context,
-                        function_calls.clone(),
-                        function_validations.clone(),
-                        send_message,
+                        env,
                     ))
                     .await
                     {
@@ -1228,10 +1525,7 @@ async fn evaluate_expression(
                     let right_value = match Box::pin(evaluate_expression(
                         *right,
                         context,
-                        secret_context,
-                        function_calls.clone(),
-                        function_validations.clone(),
-                        send_message,
+                        env,
                     ))
                     .await
                     {
@@ -1254,10 +1548,7 @@ async fn evaluate_expression(
                     let right_value = match Box::pin(evaluate_expression(
                         *right,
                         context,
-                        secret_context,
-                        function_calls.clone(),
-                        function_validations.clone(),
-                        send_message,
+                        env,
                     ))
                     .await
                     {
@@ -1266,46 +1557,54 @@ async fn evaluate_expression(
                     };
 
                     match operator {
-                        Operator::Plus => match (left_value, right_value) {
-                            (serde_json::Value::Number(l), serde_json::Value::Number(r)) => {
-                                let result = l.as_f64().unwrap_or(0.0) + r.as_f64().unwrap_or(0.0);
-                                Ok(serde_json::Value::Number(
-                                    serde_json::Number::from_f64(result)
-                                        .unwrap_or(serde_json::Number::from(0)),
-                                ))
-                            }
+                        Operator::Plus => {
+                            let sum = match (left_value, right_value) {
+                                (serde_json::Value::Number(l), serde_json::Value::Number(r)) => {
+                                    let result = l.as_f64().unwrap_or(0.0) + r.as_f64().unwrap_or(0.0);
+                                    Ok(serde_json::Value::Number(
+                                        serde_json::Number::from_f64(result)
+                                            .unwrap_or(serde_json::Number::from(0)),
+                                    ))
+                                }
 
-                            (serde_json::Value::String(l), serde_json::Value::String(r)) => {
-                                Ok(serde_json::Value::String(l + &r))
-                            }
+                                (serde_json::Value::String(l), serde_json::Value::String(r)) => {
+                                    Ok(serde_json::Value::String(l + &r))
+                                }
 
-                            (serde_json::Value::String(l), serde_json::Value::Number(r)) => {
-                                let r_str = if r.is_i64() {
-                                    r.as_i64().unwrap().to_string()
-                                } else if r.is_u64() {
-                                    r.as_u64().unwrap().to_string()
-                                } else {
-                                    r.as_f64().unwrap_or(0.0).to_string()
-                                };
-                                Ok(serde_json::Value::String(l + &r_str))
-                            }
+                                (serde_json::Value::String(l), serde_json::Value::Number(r)) => {
+                                    let r_str = if r.is_i64() {
+                                        r.as_i64().unwrap().to_string()
+                                    } else if r.is_u64() {
+                                        r.as_u64().unwrap().to_string()
+                                    } else {
+                                        r.as_f64().unwrap_or(0.0).to_string()
+                                    };
+                                    Ok(serde_json::Value::String(l + &r_str))
+                                }
 
-                            (serde_json::Value::Number(l), serde_json::Value::String(r)) => {
-                                let l_str = if l.is_i64() {
-                                    l.as_i64().unwrap().to_string()
-                                } else if l.is_u64() {
-                                    l.as_u64().unwrap().to_string()
-                                } else {
-                                    l.as_f64().unwrap_or(0.0).to_string()
-                                };
-                                Ok(serde_json::Value::String(l_str + &r))
+                                (serde_json::Value::Number(l), serde_json::Value::String(r)) => {
+                                    let l_str = if l.is_i64() {
+                                        l.as_i64().unwrap().to_string()
+                                    } else if l.is_u64() {
+                                        l.as_u64().unwrap().to_string()
+                                    } else {
+                                        l.as_f64().unwrap_or(0.0).to_string()
+                                    };
+                                    Ok(serde_json::Value::String(l_str + &r))
+                                }
+
+                                _ => Err(RuntimeError::with_location(
+                                    "Invalid operand types for addition".to_string(),
+                                    location,
+                                )),
+                            }?;
+
+                            if let Err(e) = env.tracker.check_value(&sum) {
+                                return Err(add_location_if_needed(e, &location));
                             }
 
-                            _ => Err(RuntimeError::with_location(
-                                "Invalid operand types for addition".to_string(),
-                                location,
-                            )),
-                        },
+                            Ok(sum)
+                        }
 
                         Operator::Equal => match (&left_value, &right_value) {
                             (serde_json::Value::Null, serde_json::Value::Null) => {
@@ -1457,6 +1756,71 @@ async fn evaluate_expression(
                                 location,
                             )),
                         },
+
+                        Operator::IntegerDivide => match (&left_value, &right_value) {
+                            (serde_json::Value::Number(l), serde_json::Value::Number(r)) => {
+                                let r_f64 = r.as_f64().unwrap_or(0.0);
+                                if r_f64 == 0.0 {
+                                    return Err(RuntimeError::with_location(
+                                        "Division by zero".to_string(),
+                                        location,
+                                    ));
+                                }
+                                let result = (l.as_f64().unwrap_or(0.0) / r_f64).floor();
+                                Ok(serde_json::Value::Number(
+                                    serde_json::Number::from_f64(result)
+                                        .unwrap_or(serde_json::Number::from(0)),
+                                ))
+                            }
+                            _ => Err(RuntimeError::with_location(
+                                "Invalid operand types for integer division".to_string(),
+                                location,
+                            )),
+                        },
+
+                        Operator::Modulo => match (&left_value, &right_value) {
+                            (serde_json::Value::Number(l), serde_json::Value::Number(r)) => {
+                                let r_f64 = r.as_f64().unwrap_or(0.0);
+                                if r_f64 == 0.0 {
+                                    return Err(RuntimeError::with_location(
+                                        "Modulo by zero".to_string(),
+                                        location,
+                                    ));
+                                }
+                                // Floored, like `~/`, so the result has the sign of the divisor.
+                                let remainder = l.as_f64().unwrap_or(0.0) % r_f64;
+                                let result = if remainder != 0.0 && (remainder < 0.0) != (r_f64 < 0.0) {
+                                    remainder + r_f64
+                                } else {
+                                    remainder
+                                };
+                                Ok(serde_json::Value::Number(
+                                    serde_json::Number::from_f64(result)
+                                        .unwrap_or(serde_json::Number::from(0)),
+                                ))
+                            }
+                            _ => Err(RuntimeError::with_location(
+                                "Invalid operand types for modulo".to_string(),
+                                location,
+                            )),
+                        },
+
+                        Operator::Power => match (&left_value, &right_value) {
+                            (serde_json::Value::Number(l), serde_json::Value::Number(r)) => {
+                                let result = l.as_f64().unwrap_or(0.0).powf(r.as_f64().unwrap_or(0.0));
+                                match serde_json::Number::from_f64(result) {
+                                    Some(number) => Ok(serde_json::Value::Number(number)),
+                                    None => Err(RuntimeError::with_location(
+                                        "Exponentiation result is not a finite number".to_string(),
+                                        location,
+                                    )),
+                                }
+                            }
+                            _ => Err(RuntimeError::with_location(
+                                "Invalid operand types for exponentiation".to_string(),
+                                location,
+                            )),
+                        },
                         
                         // Add these patterns to handle And and Or operators
                         // These should never be reached as they're handled in the outer match
@@ -1472,10 +1836,7 @@ async fn evaluate_expression(
             let operand_value = match Box::pin(evaluate_expression(
                 *operand,
                 context,
-                secret_context,
-                function_calls,
-                function_validations,
-                send_message,
+                env,
             ))
             .await
             {
@@ -1496,6 +1857,23 @@ async fn evaluate_expression(
 
                     Ok(serde_json::Value::Bool(!is_truthy))
                 }
+                UnaryOperator::Negate => match &operand_value {
+                    serde_json::Value::Number(n) => Ok(serde_json::Value::Number(
+                        serde_json::Number::from_f64(-n.as_f64().unwrap_or(0.0))
+                            .unwrap_or(serde_json::Number::from(0)),
+                    )),
+                    _ => Err(RuntimeError::with_location(
+                        "Invalid operand type for negation".to_string(),
+                        location,
+                    )),
+                },
+                UnaryOperator::Plus => match &operand_value {
+                    serde_json::Value::Number(_) => Ok(operand_value),
+                    _ => Err(RuntimeError::with_location(
+                        "Invalid operand type for unary plus".to_string(),
+                        location,
+                    )),
+                },
             }
         }
         Expression::MemberExpression {
@@ -1508,10 +1886,7 @@ async fn evaluate_expression(
             let obj_value = match Box::pin(evaluate_expression(
                 *object,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -1557,10 +1932,7 @@ async fn evaluate_expression(
                 let prop_value = match Box::pin(evaluate_expression(
                     *prop_expr,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
                 ))
                 .await
                 {
@@ -1667,10 +2039,7 @@ async fn evaluate_expression(
                 let value = match Box::pin(evaluate_expression(
                     element,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
                 ))
                 .await
                 {
@@ -1681,7 +2050,12 @@ async fn evaluate_expression(
                 evaluated_elements.push(value);
             }
 
-            Ok(serde_json::Value::Array(evaluated_elements))
+            let array = serde_json::Value::Array(evaluated_elements);
+            if let Err(e) = env.tracker.check_value(&array) {
+                return Err(add_location_if_needed(e, &location));
+            }
+
+            Ok(array)
         }
         Expression::ObjectExpression { properties, .. } => {
             let mut obj = serde_json::Map::new();
@@ -1700,10 +2074,7 @@ async fn evaluate_expression(
                 let value = match Box::pin(evaluate_expression(
                     property.value,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
                 ))
                 .await
                 {
@@ -1714,7 +2085,12 @@ async fn evaluate_expression(
                 obj.insert(property.key, value);
             }
 
-            Ok(serde_json::Value::Object(obj))
+            let object = serde_json::Value::Object(obj);
+            if let Err(e) = env.tracker.check_value(&object) {
+                return Err(add_location_if_needed(e, &location));
+            }
+
+            Ok(object)
         }
         Expression::MemberCallExpression {
             object,
@@ -1727,10 +2103,7 @@ async fn evaluate_expression(
             let obj = match Box::pin(evaluate_expression(
                 *object,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -1751,10 +2124,7 @@ async fn evaluate_expression(
                 let prop_value = match Box::pin(evaluate_expression(
                     *prop_expr,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
                 ))
                 .await
                 {
@@ -1779,30 +2149,31 @@ async fn evaluate_expression(
             };
 
             let mut evaluated_args = Vec::new();
+            let mut range_args = Vec::new();
             for arg in arguments {
-                let value = match Box::pin(evaluate_expression(
-                    arg,
-                    context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
-                ))
-                .await
-                {
-                    Ok(val) => val,
-                    Err(e) => return Err(add_location_if_needed(e, &location)),
+                let value = match arg {
+                    Expression::RangeExpression { start, end, step, inclusive, location: range_location }
+                        if RANGE_METHODS.contains(&method_name.as_str()) =>
+                    {
+                        let range = evaluate_range(*start, *end, step, inclusive, range_location, context, env).await?;
+                        range_args.push((evaluated_args.len(), range));
+                        range.to_value()
+                    }
+                    arg => match Box::pin(evaluate_expression(arg, context, env)).await {
+                        Ok(val) => val,
+                        Err(e) => return Err(add_location_if_needed(e, &location)),
+                    },
                 };
                 evaluated_args.push(value);
             }
 
             // Create callback executor for builtin methods
             let callback_executor: crate::builtins::CallbackExecutor = {
-                let context_callbacks = context.callbacks.clone();
+                let context = context.clone();
                 Box::new(move |callback_name: String, args: Vec<serde_json::Value>| {
-                    let context_callbacks = context_callbacks.clone();
+                    let callback_context = context.clone();
                     Box::pin(async move {
-                        if let Some(_callback) = context_callbacks.get(&callback_name) {
+                        if let Some(_callback) = callback_context.get_callback(&callback_name) {
                             // For now, return the callback name and args as a special marker
                             // This will be handled by the async builtin operation handler
                             Ok(serde_json::json!({
@@ -1826,82 +2197,41 @@ async fn evaluate_expression(
                                 op_type,
                                 map,
                                 context,
-                                secret_context,
-                                function_calls,
-                                function_validations,
-                                send_message,
+                                env,
+                                location,
                             ).await;
                         }
                     }
                     
                     debug!("Executed built-in method: {}.{}", type_name_of_val(&obj), method_name);
+                    if let Err(e) = env.tracker.check_value(&result) {
+                        return Err(add_location_if_needed(e, &location));
+                    }
                     return Ok(result);
                 },
                 Ok(None) => {
                     debug!("No built-in method found for {}.{}, checking if remote method exists", type_name_of_val(&obj), method_name);
+                    for (index, range) in range_args {
+                        evaluated_args[index] = match range_to_array(&range, env) {
+                            Ok(array) => array,
+                            Err(e) => return Err(add_location_if_needed(e, &location)),
+                        };
+                    }
                 },
                 Err(e) => {
                     return Err(e);
                 }
             }
 
-            let check_id = Uuid::new_v4().to_string();
-                
-            let (tx, rx) = oneshot::channel::<FunctionExistsResponse>();
-            
-            {
-                let mut validations = function_validations.lock().unwrap();
-                validations.insert(check_id.clone(), tx);
-            }
-            
-            let exists_request = FunctionExistsRequest {
-                id: check_id.clone(),
-                action: "is_function_exists".to_string(),
-                function_name: method_name.clone(),
-            };
-            
-            let request_json = match serde_json::to_string(&exists_request) {
-                Ok(json) => json,
-                Err(e) => {
-                    return Err(RuntimeError::with_location(
-                        format!("Serialization error: {}", e),
-                        location,
-                    ))
-                }
-            };
-            
-            match send_message(request_json).await {
-                Ok(_) => {},
+            let function_exists = match env.host.function_exists(&method_name).await {
+                Ok(exists) => exists,
                 Err(e) => return Err(add_location_if_needed(e, &location)),
-            }
-            
-            let function_exists = match timeout(Duration::from_secs(3), rx).await {
-                Ok(response_result) => match response_result {
-                    Ok(response) => response.exists,
-                    Err(_) => {
-                        debug!(
-                            "Function exists check response channel closed for '{}'",
-                            method_name
-                        );
-                        false
-                    }
-                },
-                Err(_) => {
-                    {
-                        let mut validations = function_validations.lock().unwrap();
-                        validations.remove(&check_id);
-                    }
-                    debug!("Function exists check timed out for '{}'", method_name);
-                    false
-                }
             };
             
             if !function_exists {
                 warn!("Remote method '{}' does not exist", method_name);
-                return Err(RuntimeError::FunctionNotFoundError(format!(
-                    "Method '{}' not found",
-                    method_name
-                )));
+                let error = RuntimeError::FunctionNotFoundError(format!("Method '{}' not found", method_name));
+                return Err(add_location_if_needed(error, &location));
             }
             
             debug!("Remote method '{}' exists, proceeding with call", method_name);
@@ -1909,76 +2239,13 @@ async fn evaluate_expression(
             let mut call_args = vec![obj];
             call_args.extend(evaluated_args);
 
-            let call_id = Uuid::new_v4().to_string();
-
-            let (tx, rx) = oneshot::channel::<FunctionCallResponse>();
-
-            {
-                let mut calls = function_calls.lock().unwrap();
-                calls.insert(call_id.clone(), tx);
-            }
-
-            let request = FunctionCallRequest {
-                id: call_id.clone(),
-                function_name: method_name.clone(),
-                arguments: call_args,
-                secret_context: secret_context.cloned(),
-            };
-
-            let request_json = match serde_json::to_string(&request) {
-                Ok(json) => json,
-                Err(e) => {
-                    return Err(RuntimeError::with_location(
-                        format!("Serialization error: {}", e),
-                        location,
-                    ))
-                }
-            };
-
-            match send_message(request_json).await {
-                Ok(_) => {}
-                Err(e) => return Err(add_location_if_needed(e, &location)),
-            }
-
-            match timeout(Duration::from_secs(600), rx).await {
-                Ok(response_result) => match response_result {
-                    Ok(response) => {
-                        if let Some(err) = response.error {
-                            Err(RuntimeError::with_location(
-                                format!("Remote method error: {}", err),
-                                location,
-                            ))
-                        } else {
-                            Ok(response.result)
-                        }
-                    },
-                    Err(_) => Err(RuntimeError::with_location(
-                        "Function call response channel closed".to_string(),
-                        location,
-                    )),
-                },
-                Err(_) => {
-                    {
-                        let mut calls = function_calls.lock().unwrap();
-                        calls.remove(&call_id);
-                    }
-
-                    warn!("Method call '{}' timed out after 60 seconds", method_name);
-                    Err(RuntimeError::with_location(
-                        format!("Method call '{}' timed out", method_name),
-                        location,
-                    ))
-                }
-            }
+            call_host_function(&method_name, call_args, location, env).await
         }
         Expression::AssignmentExpression { target, value, .. } => {
             let evaluated_value = match Box::pin(evaluate_expression(
                 *value,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -1986,7 +2253,16 @@ async fn evaluate_expression(
                 Err(e) => return Err(add_location_if_needed(e, &location)),
             };
 
-            context.set_variable(target, evaluated_value.clone());
+            if let Err(e) = env.tracker.check_value(&evaluated_value) {
+                return Err(add_location_if_needed(e, &location));
+            }
+
+            if !context.assign_variable(target.clone(), evaluated_value.clone()) {
+                return Err(RuntimeError::with_location(
+                    format!("Cannot assign to undeclared variable: {}", target),
+                    location,
+                ));
+            }
 
             Ok(evaluated_value)
         }
@@ -2001,10 +2277,7 @@ async fn evaluate_expression(
             let value_to_assign = match Box::pin(evaluate_expression(
                 *value,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -2025,10 +2298,7 @@ async fn evaluate_expression(
                 let prop_value = match Box::pin(evaluate_expression(
                     *prop_expr,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
                 ))
                 .await
                 {
@@ -2070,28 +2340,38 @@ async fn evaluate_expression(
 
             match object.as_ref() {
                 Expression::Identifier { name, .. } => {
-                    let mut obj_value = context
-                        .get_variable(name)
-                        .cloned()
-                        .unwrap_or(serde_json::Value::Object(serde_json::Map::new()));
+                    let mut obj_value = match context.get_variable(name) {
+                        Some(value) => value,
+                        None => {
+                            return Err(RuntimeError::with_location(
+                                format!("Cannot assign to undeclared variable: {}", name),
+                                location,
+                            ))
+                        }
+                    };
 
                     let is_array_index = final_prop_name.parse::<usize>().is_ok();
 
                     if is_array_index && obj_value.is_array() {
                         let index = final_prop_name.parse::<usize>().unwrap();
                         if let serde_json::Value::Array(ref mut arr) = obj_value {
-                            while arr.len() <= index {
-                                arr.push(serde_json::Value::Null);
+                            if let Err(e) = pad_array(arr, index, &env.tracker) {
+                                return Err(add_location_if_needed(e, &location));
                             }
-
                             arr[index] = value_to_assign.clone();
                         }
 
-                        context.set_variable(name.clone(), obj_value);
+                        if let Err(e) = env.tracker.check_value(&obj_value) {
+                            return Err(add_location_if_needed(e, &location));
+                        }
+                        context.assign_variable(name.clone(), obj_value);
                     } else if let serde_json::Value::Object(ref mut map) = obj_value {
                         map.insert(final_prop_name, value_to_assign.clone());
 
-                        context.set_variable(name.clone(), obj_value);
+                        if let Err(e) = env.tracker.check_value(&obj_value) {
+                            return Err(add_location_if_needed(e, &location));
+                        }
+                        context.assign_variable(name.clone(), obj_value);
                     } else {
                         return Err(RuntimeError::with_location(
                             format!(
@@ -2112,10 +2392,7 @@ async fn evaluate_expression(
                     let property_path = match Box::pin(extract_property_path(
                         object.as_ref(),
                         context,
-                        secret_context,
-                        &function_calls,
-                        &function_validations,
-                        send_message,
+                        env,
                     ))
                     .await
                     {
@@ -2125,17 +2402,15 @@ async fn evaluate_expression(
 
                     let root_name = &property_path[0];
 
-                    let is_next_numeric =
-                        property_path.len() > 1 && property_path[1].parse::<usize>().is_ok();
-
-                    let mut root_value =
-                        context.get_variable(root_name).cloned().unwrap_or_else(|| {
-                            if is_next_numeric {
-                                serde_json::Value::Array(Vec::new())
-                            } else {
-                                serde_json::Value::Object(serde_json::Map::new())
-                            }
-                        });
+                    let mut root_value = match context.get_variable(root_name) {
+                        Some(value) => value,
+                        None => {
+                            return Err(RuntimeError::with_location(
+                                format!("Cannot assign to undeclared variable: {}", root_name),
+                                location,
+                            ))
+                        }
+                    };
 
                     let mut full_path = property_path.clone();
                     full_path.push(final_prop_name);
@@ -2145,22 +2420,23 @@ async fn evaluate_expression(
                         &full_path,
                         1,
                         value_to_assign.clone(),
+                        &env.tracker,
                     ) {
                         Ok(_) => {}
                         Err(e) => return Err(add_location_if_needed(e, &location)),
                     };
 
-                    context.set_variable(root_name.clone(), root_value);
+                    if let Err(e) = env.tracker.check_value(&root_value) {
+                        return Err(add_location_if_needed(e, &location));
+                    }
+                    context.assign_variable(root_name.clone(), root_value);
                 }
 
                 _ => {
                     let mut obj_value = match Box::pin(evaluate_expression(
                         (*object).clone(),
                         context,
-                        secret_context,
-                        function_calls.clone(),
-                        function_validations.clone(),
-                        send_message,
+                        env,
                     ))
                     .await
                     {
@@ -2173,19 +2449,16 @@ async fn evaluate_expression(
                     if is_array_index && obj_value.is_array() {
                         let index = final_prop_name.parse::<usize>().unwrap();
                         if let serde_json::Value::Array(ref mut arr) = obj_value {
-                            while arr.len() <= index {
-                                arr.push(serde_json::Value::Null);
+                            if let Err(e) = pad_array(arr, index, &env.tracker) {
+                                return Err(add_location_if_needed(e, &location));
                             }
-
                             arr[index] = value_to_assign.clone();
                         }
                     } else if let serde_json::Value::Object(ref mut map) = obj_value {
                         map.insert(final_prop_name, value_to_assign.clone());
                     } else {
                         return Err(RuntimeError::with_location(
-                            format!(
-                                "Cannot set property on non-object/non-array or invalid index type"
-                            ),
+                            "Cannot set property on non-object/non-array or invalid index type".to_string(),
                             location,
                         ));
                     }
@@ -2198,10 +2471,7 @@ async fn evaluate_expression(
             let obj_value = match Box::pin(evaluate_expression(
                 *object,
                 context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await
             {
@@ -2240,21 +2510,145 @@ async fn evaluate_expression(
                 "hash": CALLBACK_REFERENCE_HASH
             }))
         }
+        Expression::RangeExpression { start, end, step, inclusive, .. } => {
+            let range = evaluate_range(*start, *end, step, inclusive, location, context, env).await?;
+            range_to_array(&range, env).map_err(|e| add_location_if_needed(e, &location))
+        }
         Expression::BooleanLiteral { value, .. } => Ok(serde_json::Value::Bool(value)),
         Expression::NullLiteral { .. } => Ok(serde_json::Value::Null),
     }
 }
 
+async fn evaluate_range(
+    start: Expression,
+    end: Expression,
+    step: Option<Box<Expression>>,
+    inclusive: bool,
+    location: SourceLocation,
+    context: &mut ExecutionContext,
+    env: &ExecutionEnv<'_>,
+) -> Result<RangeValue, RuntimeError> {
+    let mut bounds = Vec::with_capacity(3);
+    for bound in [start, end].into_iter().chain(step.map(|step| *step)) {
+        let value = match Box::pin(evaluate_expression(bound, context, env)).await {
+            Ok(val) => val,
+            Err(e) => return Err(add_location_if_needed(e, &location)),
+        };
+
+        match value.as_f64() {
+            Some(number) => bounds.push(number),
+            None => {
+                return Err(RuntimeError::with_location(
+                    "Range bounds and step must be numbers".to_string(),
+                    location,
+                ))
+            }
+        }
+    }
+
+    let step = bounds.get(2).copied().unwrap_or(1.0);
+    RangeValue::new(bounds[0], bounds[1], step, inclusive)
+        .map_err(|message| RuntimeError::with_location(message, location))
+}
+
+/// Builds the array of the numbers of a range used outside of a loop or a
+/// slice. Each number counts as a loop iteration, so a large range is
+/// rejected before it is built.
+fn range_to_array(range: &RangeValue, env: &ExecutionEnv<'_>) -> Result<serde_json::Value, RuntimeError> {
+    let mut numbers = Vec::new();
+    for (iteration, number) in (1..).zip(range.iter()) {
+        env.tracker.check_loop_iteration(iteration)?;
+        numbers.push(range::number_value(number));
+    }
+
+    let array = serde_json::Value::Array(numbers);
+    env.tracker.check_value(&array)?;
+    Ok(array)
+}
+
+/// Evaluates the elements of the array literal passed to `all` concurrently,
+/// so that the remote calls among them wait on the client together. Any
+/// other array is returned as it is.
+async fn evaluate_all(
+    arguments: Vec<Expression>,
+    context: &mut ExecutionContext,
+    env: &ExecutionEnv<'_>,
+    location: SourceLocation,
+) -> Result<serde_json::Value, RuntimeError> {
+    let [argument] = <[Expression; 1]>::try_from(arguments).map_err(|arguments| {
+        RuntimeError::with_location(
+            format!("all expects 1 argument (array), got {}", arguments.len()),
+            location,
+        )
+    })?;
+
+    let elements = match argument {
+        Expression::ArrayExpression { elements, .. } => elements,
+        argument => {
+            return match Box::pin(evaluate_expression(argument, context, env)).await {
+                Ok(value) if value.is_array() => Ok(value),
+                Ok(_) => Err(RuntimeError::with_location(
+                    "all expects an array".to_string(),
+                    location,
+                )),
+                Err(e) => Err(add_location_if_needed(e, &location)),
+            };
+        }
+    };
+
+    let mut tasks = Vec::with_capacity(elements.len());
+    for element in elements {
+        let mut element_context = context.clone();
+        tasks.push(async move { Box::pin(evaluate_expression(element, &mut element_context, env)).await });
+    }
+
+    let array = serde_json::Value::Array(
+        run_concurrently(tasks, env)
+            .await
+            .map_err(|e| add_location_if_needed(e, &location))?,
+    );
+    if let Err(e) = env.tracker.check_value(&array) {
+        return Err(add_location_if_needed(e, &location));
+    }
+
+    Ok(array)
+}
+
+/// Runs `tasks` at the same time, at most `max_concurrency` at once, and
+/// returns their results in order. Stops at the first error.
+async fn run_concurrently<F>(
+    tasks: Vec<F>,
+    env: &ExecutionEnv<'_>,
+) -> Result<Vec<serde_json::Value>, RuntimeError>
+where
+    F: std::future::Future<Output = Result<serde_json::Value, RuntimeError>>,
+{
+    let max_concurrency = env.tracker.limits().max_concurrency.unwrap_or(usize::MAX).max(1);
+
+    futures_util::stream::iter(tasks)
+        .buffered(max_concurrency)
+        .try_collect()
+        .await
+}
+
+/// Runs a callback in a new scope nested in the scope the callback was
+/// declared in. Arguments are evaluated in the caller's context.
 async fn execute_callback(
-    callback: CallbackFunction,
+    closure: Closure,
     arguments: Vec<Expression>,
     parent_context: &mut ExecutionContext,
-    secret_context: Option<&serde_json::Value>,
-    function_calls: PendingFunctionCalls,
-    function_validations: PendingFunctionValidations,
-    send_message: &impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
+    frame_name: String,
+    call_location: SourceLocation,
 ) -> Result<serde_json::Value, RuntimeError> {
-    let mut callback_context = ExecutionContext::with_parent(parent_context);
+    let call_depth = parent_context.call_depth + 1;
+    env.tracker.check_call_depth(call_depth)?;
+
+    let Closure { callback, scope } = closure;
+    let mut callback_context = ExecutionContext {
+        call_depth,
+        ..ExecutionContext::with_parent(&scope)
+    };
 
     if arguments.len() < callback.params.len() {
         return Err(RuntimeError::ExecutionError(format!(
@@ -2270,10 +2664,7 @@ async fn execute_callback(
             let arg_value = Box::pin(evaluate_expression(
                 arguments[i].clone(),
                 parent_context,
-                secret_context,
-                function_calls.clone(),
-                function_validations.clone(),
-                send_message,
+                env,
             ))
             .await?;
 
@@ -2281,15 +2672,18 @@ async fn execute_callback(
         }
     }
 
-    let result = execute_block(
+    let result = execute_block_in(
         callback.body,
         &mut callback_context,
-        secret_context,
-        function_calls,
-        function_validations,
-        send_message,
+        env,
     )
-    .await?;
+    .await
+    .map_err(|e| {
+        e.with_frame(CallFrame {
+            name: frame_name,
+            location: call_location,
+        })
+    })?;
 
     let return_value = match result {
         Some(value) => {
@@ -2317,10 +2711,8 @@ async fn handle_async_builtin_operation(
     op_type: &str,
     params: &serde_json::Map<String, serde_json::Value>,
     context: &mut ExecutionContext,
-    secret_context: Option<&serde_json::Value>,
-    function_calls: PendingFunctionCalls,
-    function_validations: PendingFunctionValidations,
-    send_message: &impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>>,
+    env: &ExecutionEnv<'_>,
+    location: SourceLocation,
 ) -> Result<serde_json::Value, RuntimeError> {
     let callback_name = params.get("callback_name")
         .and_then(|v| v.as_str())
@@ -2331,7 +2723,6 @@ async fn handle_async_builtin_operation(
         .ok_or_else(|| RuntimeError::CallbackExecutionError("Missing array parameter".to_string()))?;
     
     let callback = context.get_callback(callback_name)
-        .cloned()
         .ok_or_else(|| RuntimeError::CallbackExecutionError(format!("Callback '{}' not found", callback_name)))?;
     
     match op_type {
@@ -2347,16 +2738,33 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
                 
                 results.push(result);
             }
             Ok(serde_json::Value::Array(results))
         },
+        "parallelMap" => {
+            let mut tasks = Vec::with_capacity(array.len());
+            for (index, item) in array.iter().enumerate() {
+                let args = vec![
+                    value_to_expression(item.clone()),
+                    value_to_expression(serde_json::Value::Number(index.into())),
+                ];
+                let callback = callback.clone();
+                let mut item_context = context.clone();
+                let frame_name = callback_frame_name(callback_name, Some(op_type));
+
+                tasks.push(async move {
+                    execute_callback(callback, args, &mut item_context, env, frame_name, location).await
+                });
+            }
+
+            Ok(serde_json::Value::Array(run_concurrently(tasks, env).await?))
+        },
         "filter" => {
             let mut results = Vec::new();
             for (index, item) in array.iter().enumerate() {
@@ -2369,10 +2777,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
                 
                 // Check if result is truthy
@@ -2402,10 +2809,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
             }
             Ok(serde_json::Value::Null)
@@ -2440,10 +2846,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
             }
             
@@ -2460,10 +2865,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
                 
                 // Check if result is truthy
@@ -2493,10 +2897,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
                 
                 // Check if result is truthy
@@ -2526,10 +2929,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
                 
                 // Check if result is truthy
@@ -2559,10 +2961,9 @@ async fn handle_async_builtin_operation(
                     callback.clone(),
                     args,
                     context,
-                    secret_context,
-                    function_calls.clone(),
-                    function_validations.clone(),
-                    send_message,
+                    env,
+                    callback_frame_name(callback_name, Some(op_type)),
+                    location,
                 ).await?;
                 
                 // Check if result is truthy
@@ -2585,6 +2986,13 @@ async fn handle_async_builtin_operation(
     }
 }
 
+fn callback_frame_name(callback_name: &str, builtin: Option<&str>) -> String {
+    match builtin {
+        Some(op_type) => format!("{} (in {})", callback_name, op_type),
+        None => callback_name.to_string(),
+    }
+}
+
 fn value_to_expression(value: serde_json::Value) -> Expression {
     
     
@@ -2627,3 +3035,423 @@ fn value_to_expression(value: serde_json::Value) -> Expression {
         },
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::runtime_error_response;
+    use crate::messages::{ErrorDetails, ResponseError};
+    use crate::{ExecutionLimits, Interpreter, RuntimeError};
+    use serde_json::{json, Map, Value};
+    use hexput_ast_api::feature_flags::FeatureFlags;
+    use hexput_ast_api::parser::Parser;
+
+    async fn run(code: &str) -> Result<Value, RuntimeError> {
+        Interpreter::new().execute_code(code, Map::new()).await
+    }
+
+    async fn run_with_limits(code: &str, limits: ExecutionLimits) -> Result<Value, RuntimeError> {
+        Interpreter::new()
+            .with_limits(limits)
+            .execute_code(code, Map::new())
+            .await
+    }
+
+    fn value_size_limit(max_value_size: usize) -> ExecutionLimits {
+        ExecutionLimits {
+            max_value_size: Some(max_value_size),
+            ..ExecutionLimits::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_functions_and_context_are_available() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_function("add", |args| Ok(json!(args.iter().filter_map(Value::as_f64).sum::<f64>())));
+        let mut context = Map::new();
+        context.insert("base".to_string(), json!(10));
+
+        let result = interpreter.execute_code("res add(base, 2, 3);", context).await.unwrap();
+        assert_eq!(result, json!(15.0));
+        assert!(run("res add(1, 2);").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn parse_errors_keep_their_location() {
+        let error = run("vl a = 1;\nres (a;").await.unwrap_err();
+        assert!(matches!(error, RuntimeError::ParseError(_)));
+        assert_eq!(error.location().map(|l| l.start_line), Some(2));
+    }
+
+    #[tokio::test]
+    async fn statement_limit_stops_long_loops() {
+        let limits = ExecutionLimits {
+            max_statements: Some(100),
+            ..ExecutionLimits::default()
+        };
+        let mut context = Map::new();
+        context.insert("items".to_string(), json!(vec![1; 1000]));
+        let error = Interpreter::new()
+            .with_limits(limits)
+            .execute_code("vl n = 0; loop item in items { n = n + item; } res n;", context)
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RuntimeError::ResourceLimitExceeded { .. }));
+
+        let result = Interpreter::new()
+            .with_limits(limits)
+            .execute_code("vl n = 1; res n + 1;", Map::new())
+            .await
+            .unwrap();
+        assert_eq!(result, json!(2.0));
+    }
+
+    #[tokio::test]
+    async fn errors_carry_the_callbacks_they_unwind() {
+        let code = "cb inner() {\n  res nope();\n}\ncb outer() {\n  res inner();\n}\nres outer();";
+        let error = run(code).await.unwrap_err();
+        assert_eq!(error.kind(), "FunctionNotFoundError");
+        assert_eq!(error.location().map(|l| l.start_line), Some(2));
+
+        let frames: Vec<_> = error
+            .call_stack()
+            .iter()
+            .map(|frame| (frame.name.as_str(), frame.location.start_line))
+            .collect();
+        assert_eq!(frames, [("inner", 5), ("outer", 7)]);
+    }
+
+    #[tokio::test]
+    async fn in_bounds_array_writes_are_not_charged_as_padding() {
+        let mut context = Map::new();
+        context.insert("a".to_string(), json!(vec![""; 1000]));
+        let result = Interpreter::new()
+            .with_limits(value_size_limit(4000))
+            .execute_code("a[999] = \"x\"; res a[999];", context)
+            .await
+            .unwrap();
+        assert_eq!(result, json!("x"));
+    }
+
+    #[tokio::test]
+    async fn numeric_object_keys_are_not_charged_as_padding() {
+        let result = run_with_limits("vl o = {}; o[\"1000\"] = 1; res o;", value_size_limit(100))
+            .await
+            .unwrap();
+        assert_eq!(result, json!({ "1000": 1.0 }));
+    }
+
+    #[tokio::test]
+    async fn array_padding_is_checked_before_growing() {
+        let error = run_with_limits("vl a = []; a[10000] = 1;", value_size_limit(4000))
+            .await
+            .unwrap_err();
+        assert!(matches!(error, RuntimeError::ResourceLimitExceeded { .. }));
+
+        let result = run("vl a = [1]; a[3] = 4; res a;").await.unwrap();
+        assert_eq!(result, json!([1.0, null, null, 4.0]));
+    }
+
+    #[tokio::test]
+    async fn array_loops_can_be_aborted() {
+        let mut context = Map::new();
+        context.insert("items".to_string(), Value::Array(vec![json!(1); 1_000_000]));
+        let execution = tokio::spawn(async move {
+            Interpreter::new()
+                .execute_code("vl n = 0; loop item in items { n = n + item; } res n;", context)
+                .await
+        });
+
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        execution.abort();
+        assert!(execution.await.unwrap_err().is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn structured_errors_describe_kind_and_location() {
+        let error = run("vl a = 1;\nres a + missing;").await.unwrap_err();
+        match runtime_error_response(&error, true) {
+            ResponseError::Details(details) => {
+                assert_eq!(details.kind, "ExecutionError");
+                assert_eq!(details.message, "Undefined variable: missing");
+                let location = details.location.unwrap();
+                assert_eq!((location.line, location.column), (2, 9));
+            }
+            other => panic!("expected structured details, got {:?}", other),
+        }
+        assert!(matches!(
+            runtime_error_response(&error, false),
+            ResponseError::Message(message) if message.starts_with("Execution error at line 2, column 9")
+        ));
+    }
+
+    #[tokio::test]
+    async fn located_errors_keep_their_kind() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_function("fail", |_| Err("boom".to_string()));
+
+        let error = interpreter.execute_code("vl a = 1;\nres fail();", Map::new()).await.unwrap_err();
+        let details = ErrorDetails::from(&error);
+        assert_eq!(details.kind, "FunctionCallError");
+        assert_eq!(details.message, "boom");
+        assert_eq!(details.location.map(|l| l.line), Some(2));
+
+        let error = run("res nope();").await.unwrap_err();
+        let details = ErrorDetails::from(&error);
+        assert_eq!(details.kind, "FunctionNotFoundError");
+        assert!(details.location.is_some());
+
+        let error = run("vl x = [1]; loop i in x { x.push(i); }").await.unwrap_err();
+        let details = ErrorDetails::from(&error);
+        assert_eq!(details.kind, "FunctionNotFoundError");
+        assert_eq!(details.message, "Method 'push' not found");
+    }
+
+    #[tokio::test]
+    async fn arithmetic_operators_follow_precedence() {
+        let code = "res [7 % 3, 7 ~/ 2, 2 ** 3, 1 + 2 * 3 ** 2, 10 - 9 ~/ 2 % 3];";
+        assert_eq!(run(code).await.unwrap(), json!([1.0, 3.0, 8.0, 19.0, 9.0]));
+    }
+
+    #[tokio::test]
+    async fn modulo_matches_integer_division() {
+        let code = "vl a = -7; vl b = 2; vl c = -2; res [a % b, a ~/ b, (a ~/ b) * b + a % b, 7 % c, 7 ~/ c, -7 % 2];";
+        assert_eq!(run(code).await.unwrap(), json!([1.0, -4.0, -7.0, -1.0, -4.0, 1.0]));
+    }
+
+    #[tokio::test]
+    async fn exponents_are_right_associative() {
+        let code = "vl n = 3; res [2 ** n ** 2, 2 * n ** 2, 2 ** -1];";
+        assert_eq!(run(code).await.unwrap(), json!([512.0, 18.0, 0.5]));
+    }
+
+    #[tokio::test]
+    async fn unary_operators_apply_to_any_expression() {
+        let code = "vl width = 6; res [-(width / 2), +width, -2 ** 2, -(-width)];";
+        assert_eq!(run(code).await.unwrap(), json!([-3.0, 6.0, -4.0, 6.0]));
+    }
+
+    #[tokio::test]
+    async fn while_loops_run_until_their_condition_is_false() {
+        let code = "vl i = 0; vl sum = 0; loop while i < 5 { i = i + 1; if i == 2 { continue; } sum = sum + i; } res [i, sum];";
+        assert_eq!(run(code).await.unwrap(), json!([5.0, 13.0]));
+
+        let code = "vl i = 0; loop while true { i = i + 1; if i == 3 { end; } } res i;";
+        assert_eq!(run(code).await.unwrap(), json!(3.0));
+    }
+
+    #[tokio::test]
+    async fn range_loops_follow_step_and_inclusive_bounds() {
+        let code = "vl out = []; loop i in 0..3 { out[out.length()] = i; } loop i in 0..=6 step 3 { out[out.length()] = i; } loop i in 3..0 step -1 { out[out.length()] = i; } res out;";
+        assert_eq!(run(code).await.unwrap(), json!([0.0, 1.0, 2.0, 0.0, 3.0, 6.0, 3.0, 2.0, 1.0]));
+
+        let code = "vl out = []; loop index, i in 10..13 { out[out.length()] = index; } res out;";
+        assert_eq!(run(code).await.unwrap(), json!([0.0, 1.0, 2.0]));
+    }
+
+    #[tokio::test]
+    async fn ranges_select_slice_indices() {
+        assert_eq!(run("res [10, 20, 30, 40].slice(1..=2);").await.unwrap(), json!([20.0, 30.0]));
+        assert_eq!(run("res \"hexput\".substring(0..6 step 2);").await.unwrap(), json!("hxu"));
+    }
+
+    #[tokio::test]
+    async fn ranges_outside_loops_are_arrays() {
+        assert_eq!(run("res 0..3;").await.unwrap(), json!([0.0, 1.0, 2.0]));
+        assert_eq!(run("vl r = 0..=4 step 2; vl out = []; loop i in r { out[out.length()] = i; } res out;").await.unwrap(), json!([0.0, 2.0, 4.0]));
+        assert_eq!(run("res [1..3];").await.unwrap(), json!([[1.0, 2.0]]));
+
+        let mut interpreter = Interpreter::new();
+        interpreter.register_function("echo", |args| Ok(Value::Array(args)));
+        interpreter.register_function("slice", |args| Ok(Value::Array(args)));
+        let result = interpreter.execute_code("res echo(0..2);", Map::new()).await.unwrap();
+        assert_eq!(result, json!([[0.0, 1.0]]));
+        let result = interpreter.execute_code("res {}.slice(0..2);", Map::new()).await.unwrap();
+        assert_eq!(result, json!([{}, [0.0, 1.0]]));
+    }
+
+    #[tokio::test]
+    async fn large_ranges_are_not_built() {
+        let limits = ExecutionLimits {
+            max_loop_iterations: Some(100),
+            ..ExecutionLimits::default()
+        };
+        let error = run_with_limits("res 0..1000000000;", limits).await.unwrap_err();
+        assert_eq!(error.kind(), "ResourceLimitExceeded");
+    }
+
+    #[tokio::test]
+    async fn object_loops_bind_keys_or_keys_and_values() {
+        let code = "vl o = { a: 1, b: 2 }; vl keys = []; loop k in o { keys[keys.length()] = k; } res keys;";
+        assert_eq!(run(code).await.unwrap(), json!(["a", "b"]));
+
+        let code = "vl o = { a: 1, b: 2 }; vl copy = {}; loop k, v in o { copy[k] = v * 10; } res copy;";
+        assert_eq!(run(code).await.unwrap(), json!({ "a": 10.0, "b": 20.0 }));
+
+        let code = "vl out = []; loop i, x in [\"x\", \"y\"] { out[i] = x + i; } res out;";
+        assert_eq!(run(code).await.unwrap(), json!(["x0", "y1"]));
+    }
+
+    #[tokio::test]
+    async fn callbacks_write_to_the_scopes_they_close_over() {
+        let code = "vl total = 0; [1, 2, 3].forEach(cb(item) { total = total + item; }); res total;";
+        assert_eq!(run(code).await.unwrap(), json!(6.0));
+
+        let code = "vl count = 0; cb bump() { count = count + 1; } bump(); bump(); res count;";
+        assert_eq!(run(code).await.unwrap(), json!(2.0));
+    }
+
+    #[tokio::test]
+    async fn callbacks_resolve_names_where_they_are_declared() {
+        let code = "vl x = \"outer\"; cb show() { res x; } cb run() { vl x = \"inner\"; res show(); } res run();";
+        assert_eq!(run(code).await.unwrap(), json!("outer"));
+    }
+
+    #[tokio::test]
+    async fn assignments_update_the_declaring_scope() {
+        assert_eq!(run("vl a = 1; if true { a = 2; } res a;").await.unwrap(), json!(2.0));
+        assert_eq!(run("vl a = 1; if true { vl a = 5; a = 6; } res a;").await.unwrap(), json!(1.0));
+        assert_eq!(run("vl a = 1; loop i in [1, 2] { a = a + i; } res a;").await.unwrap(), json!(4.0));
+    }
+
+    #[tokio::test]
+    async fn undeclared_and_redeclared_variables_are_errors() {
+        let error = run("b = 1;").await.unwrap_err();
+        assert!(error.message().contains("undeclared"), "{}", error);
+
+        let error = run("vl a = 1; vl a = 2;").await.unwrap_err();
+        assert!(error.message().contains("already declared"), "{}", error);
+
+        assert!(run("vl a = 1; if true { vl a = 2; }").await.is_ok());
+        // The optimizer must not merge the block into the outer scope.
+        assert_eq!(run("if true { vl a = 1; } vl a = 2; res a;").await.unwrap(), json!(2.0));
+    }
+
+    #[tokio::test]
+    async fn constant_folding_matches_runtime_evaluation() {
+        let cases = [
+            ("-7", "%", "2"),
+            ("7", "%", "-2"),
+            ("7", "~/", "-2"),
+            ("10", "/", "4"),
+            ("2", "**", "10"),
+            ("\"a\"", "+", "1"),
+            ("\"a\"", "<", "\"b\""),
+            ("1", "==", "1"),
+            ("0", "&&", "true"),
+            ("\"\"", "||", "null"),
+        ];
+        for (left, operator, right) in cases {
+            let folded = run(&format!("res {} {} {};", left, operator, right)).await.unwrap();
+            let evaluated = run(&format!("vl l = {}; vl r = {}; res l {} r;", left, right, operator)).await.unwrap();
+            assert_eq!(folded, evaluated, "{} {} {}", left, operator, right);
+        }
+
+        for (folded, evaluated) in [("res !0;", "vl x = 0; res !x;"), ("res -(3);", "vl x = 3; res -x;")] {
+            assert_eq!(run(folded).await.unwrap(), run(evaluated).await.unwrap(), "{}", folded);
+        }
+    }
+
+    #[tokio::test]
+    async fn empty_branches_and_loops_still_evaluate_their_conditions() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let mut interpreter = Interpreter::new();
+        let counter = calls.clone();
+        interpreter.register_function("check", move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(json!(true))
+        });
+        let counter = calls.clone();
+        interpreter.register_function("load", move |_| {
+            counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(json!([1, 2]))
+        });
+
+        interpreter
+            .execute_code("if check() {} loop item in load() {}", Map::new())
+            .await
+            .unwrap();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn optimized_programs_behave_like_unoptimized_ones() {
+        let cases = [
+            "end; res 5;",
+            "continue; res 5;",
+            "{ if true { end; } res 1; } res 5;",
+            "vl n = 0; loop i in [1, 2, 3] { if i == 2 { end; n = 10; } n = n + i; } res n;",
+            "if undefined_name {}",
+            "loop i in 5 {}",
+        ];
+        for code in cases {
+            let tokens = hexput_ast_api::lexer::tokenize(code).unwrap();
+            let program = Parser::new(&tokens, FeatureFlags::default(), code).parse_program().unwrap();
+            let unoptimized = Interpreter::new().execute(&program, Map::new()).await;
+            let optimized = run(code).await;
+            match (optimized, unoptimized) {
+                (Ok(optimized), Ok(unoptimized)) => assert_eq!(optimized, unoptimized, "{}", code),
+                (Err(optimized), Err(unoptimized)) => assert_eq!(optimized.to_string(), unoptimized.to_string(), "{}", code),
+                (optimized, unoptimized) => panic!("{}: {:?} optimized, {:?} unoptimized", code, optimized, unoptimized),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn serialized_programs_run_without_source_mapping() {
+        let code = "vl total = 0; loop i in [1, 2, 3] { total = total + i; } res { total: total };";
+        let program = hexput_ast_api::process_code(code, FeatureFlags::default()).unwrap();
+
+        for include_source_mapping in [true, false] {
+            let json = hexput_ast_api::to_json_string(&program, include_source_mapping).unwrap();
+            let program: hexput_ast_api::ast_structs::Program = serde_json::from_str(&json).unwrap();
+            let result = Interpreter::new().execute(&program, Map::new()).await.unwrap();
+            assert_eq!(result, json!({ "total": 6.0 }));
+        }
+    }
+
+    #[test]
+    fn manifests_reject_invalid_calls_before_running() {
+        let functions = [hexput_ast_api::analysis::FunctionSignature::named("save")];
+        let program = hexput_ast_api::process_code("save(1);\nres load();", FeatureFlags::default()).unwrap();
+
+        let error = super::validate_calls(&program, &functions).unwrap_err();
+        assert_eq!(error.kind(), "ValidationError");
+        assert_eq!(error.message(), "Function 'load' not found");
+        assert_eq!(error.location().map(|l| l.start_line), Some(2));
+    }
+
+    #[tokio::test]
+    async fn all_and_parallel_map_keep_the_order_of_their_items() {
+        let limits = ExecutionLimits {
+            max_concurrency: Some(2),
+            ..ExecutionLimits::default()
+        };
+        let mut interpreter = Interpreter::new().with_limits(limits);
+        interpreter.register_function("double", |args| Ok(json!(args[0].as_f64().unwrap_or(0.0) * 2.0)));
+
+        let code = "vl a = all([double(1), double(2), 3]); vl b = [1, 2, 3].parallelMap(cb(x) { res double(x) + 1; }); res [a, b, all(a)];";
+        let result = interpreter.execute_code(code, Map::new()).await.unwrap();
+        assert_eq!(result, json!([[2.0, 4.0, 3.0], [3.0, 5.0, 7.0], [2.0, 4.0, 3.0]]));
+
+        let error = run("res all([1, nope()]);").await.unwrap_err();
+        assert_eq!(error.kind(), "FunctionNotFoundError");
+    }
+
+    #[tokio::test]
+    async fn call_depth_is_counted_per_branch() {
+        let limits = ExecutionLimits {
+            max_call_depth: Some(2),
+            ..ExecutionLimits::default()
+        };
+        let code = "cb work(x) { vl n = 0; loop i in 0..1000 { n = n + 1; } res n; } \
+                    cb twice(x) { res work(x) * 2; } \
+                    res [1, 2, 3].parallelMap(twice);";
+        assert_eq!(run_with_limits(code, limits).await.unwrap(), json!([2000.0, 2000.0, 2000.0]));
+
+        let code = "cb down(n) { if n > 0 { res down(n - 1); } res 0; } res down(2);";
+        assert!(matches!(
+            run_with_limits(code, limits).await,
+            Err(RuntimeError::WithCallStack { error, .. }) if matches!(*error, RuntimeError::ResourceLimitExceeded { .. })
+        ));
+    }
+}
@@ -0,0 +1,434 @@
+use crate::error::RuntimeError;
+use crate::messages::{
+    FunctionCallBatchRequest, FunctionCallRequest, FunctionCallResponse, FunctionExistsRequest,
+    FunctionExistsResponse, FunctionTimeoutOptions,
+};
+use futures_util::future::BoxFuture;
+use hexput_ast_api::analysis::FunctionSignature;
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::sync::oneshot;
+use tokio::time::{timeout, Duration};
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+pub type PendingFunctionCalls = Arc<Mutex<HashMap<String, oneshot::Sender<FunctionCallResponse>>>>;
+pub type PendingFunctionValidations =
+    Arc<Mutex<HashMap<String, oneshot::Sender<FunctionExistsResponse>>>>;
+
+/// Answers of earlier `is_function_exists` checks, by function name.
+pub type FunctionExistsCache = Arc<Mutex<HashMap<String, bool>>>;
+
+/// How long to wait for the client to answer, unless a request or the
+/// function's manifest entry says otherwise.
+#[derive(Debug, Clone, Copy)]
+pub struct FunctionTimeouts {
+    pub function_exists: Duration,
+    pub function_call: Duration,
+}
+
+impl Default for FunctionTimeouts {
+    fn default() -> Self {
+        Self {
+            function_exists: Duration::from_secs(3),
+            function_call: Duration::from_secs(600),
+        }
+    }
+}
+
+impl FunctionTimeouts {
+    /// Replaces the timeouts a request sets.
+    pub fn with_overrides(self, options: &FunctionTimeoutOptions) -> Self {
+        Self {
+            function_exists: options
+                .function_exists_ms
+                .map_or(self.function_exists, Duration::from_millis),
+            function_call: options
+                .function_call_ms
+                .map_or(self.function_call, Duration::from_millis),
+        }
+    }
+}
+
+/// How the requests of a connection find and call the functions the client
+/// handles.
+#[derive(Clone, Default)]
+pub struct RemoteFunctions {
+    pub exists_cache: FunctionExistsCache,
+    /// Set when the client declared in the handshake that it handles every
+    /// function called, so no existence checks are sent.
+    pub skip_existence_check: bool,
+    /// Set when the client accepts several function calls in one message.
+    pub batch_calls: bool,
+    pub timeouts: FunctionTimeouts,
+}
+
+pub type MessageSender =
+    dyn Fn(String) -> BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync;
+
+/// Removes a pending request from its map when dropped, so entries do not
+/// outlive a call that timed out or whose execution was cancelled.
+struct PendingEntry<'a, T> {
+    pending: &'a Mutex<HashMap<String, oneshot::Sender<T>>>,
+    id: String,
+}
+
+impl<T> Drop for PendingEntry<'_, T> {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// Resolves calls to functions that are not callbacks defined by the script.
+///
+/// `call_function` reports errors raised by the function itself as
+/// `RuntimeError::FunctionCallError`. Both methods report a client that did
+/// not answer in time as `RuntimeError::TimeoutError`.
+pub trait FunctionHost: Send + Sync {
+    fn function_exists<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<bool, RuntimeError>>;
+
+    fn call_function<'a>(
+        &'a self,
+        name: &'a str,
+        arguments: Vec<Value>,
+        secret_context: Option<&'a Value>,
+    ) -> BoxFuture<'a, Result<Value, RuntimeError>>;
+}
+
+/// Forwards function calls to the WebSocket client using the
+/// `is_function_exists` / function call protocol.
+pub struct RemoteFunctionHost {
+    function_calls: PendingFunctionCalls,
+    function_validations: PendingFunctionValidations,
+    send_message: Arc<MessageSender>,
+    manifest: Option<HashSet<String>>,
+    exists_cache: Option<FunctionExistsCache>,
+    skip_existence_check: bool,
+    timeouts: FunctionTimeouts,
+    /// Call timeouts of the manifest entries that set one.
+    call_timeouts: HashMap<String, Duration>,
+    /// Calls waiting to be sent together, when batching.
+    batch: Option<Mutex<Vec<FunctionCallRequest>>>,
+}
+
+impl RemoteFunctionHost {
+    pub fn new(
+        function_calls: PendingFunctionCalls,
+        function_validations: PendingFunctionValidations,
+        send_message: Arc<MessageSender>,
+    ) -> Self {
+        Self {
+            function_calls,
+            function_validations,
+            send_message,
+            manifest: None,
+            exists_cache: None,
+            skip_existence_check: false,
+            timeouts: FunctionTimeouts::default(),
+            call_timeouts: HashMap::new(),
+            batch: None,
+        }
+    }
+
+    pub fn with_timeouts(mut self, timeouts: FunctionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Sends the calls made at the same time, such as those of `all` or
+    /// `parallelMap`, in one `FunctionCallBatchRequest`.
+    pub fn with_batching(mut self) -> Self {
+        self.batch = Some(Mutex::new(Vec::new()));
+        self
+    }
+
+    async fn send_call(&self, request: FunctionCallRequest) -> Result<(), RuntimeError> {
+        let Some(batch) = &self.batch else {
+            return (self.send_message)(serde_json::to_string(&request)?).await;
+        };
+
+        batch.lock().unwrap().push(request);
+        // Let the other calls started in this round join the batch. The
+        // first call to resume sends it; the batch is empty for the others.
+        tokio::task::yield_now().await;
+        let mut calls = std::mem::take(&mut *batch.lock().unwrap());
+
+        let message = match calls.len() {
+            0 => return Ok(()),
+            1 => serde_json::to_string(&calls.remove(0))?,
+            count => {
+                debug!("Sending {} function calls in one batch", count);
+                serde_json::to_string(&FunctionCallBatchRequest {
+                    id: Uuid::new_v4().to_string(),
+                    calls,
+                })?
+            }
+        };
+        (self.send_message)(message).await
+    }
+
+    /// Keeps the answers of existence checks in `cache` and reuses them
+    /// instead of asking the client again.
+    pub fn with_exists_cache(mut self, cache: FunctionExistsCache) -> Self {
+        self.exists_cache = Some(cache);
+        self
+    }
+
+    /// Treats every function as existing, leaving unknown ones to fail when
+    /// the client answers the call.
+    pub fn skip_existence_check(mut self) -> Self {
+        self.skip_existence_check = true;
+        self
+    }
+
+    /// Answers existence checks for the functions the client declared
+    /// without asking it, and waits on calls for the `timeout_ms` their
+    /// entries set. Other names are still checked with the client.
+    pub fn with_manifest(mut self, functions: &[FunctionSignature]) -> Self {
+        self.manifest = Some(functions.iter().map(|signature| signature.name.clone()).collect());
+        self.call_timeouts = functions
+            .iter()
+            .filter_map(|signature| {
+                let timeout = Duration::from_millis(signature.timeout_ms?);
+                Some((signature.name.clone(), timeout))
+            })
+            .collect();
+        self
+    }
+}
+
+fn timeout_error(name: &str, message: String) -> RuntimeError {
+    RuntimeError::TimeoutError {
+        message,
+        function: name.to_string(),
+        location: None,
+    }
+}
+
+impl FunctionHost for RemoteFunctionHost {
+    fn function_exists<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<bool, RuntimeError>> {
+        Box::pin(async move {
+            if self.manifest.as_ref().is_some_and(|manifest| manifest.contains(name)) {
+                return Ok(true);
+            }
+            if self.skip_existence_check {
+                return Ok(true);
+            }
+            if let Some(exists) = self
+                .exists_cache
+                .as_ref()
+                .and_then(|cache| cache.lock().unwrap().get(name).copied())
+            {
+                debug!("Using cached existence of remote function '{}': {}", name, exists);
+                return Ok(exists);
+            }
+
+            debug!("Checking if remote function exists: {}", name);
+
+            let check_id = Uuid::new_v4().to_string();
+
+            let (tx, rx) = oneshot::channel::<FunctionExistsResponse>();
+
+            {
+                let mut validations = self.function_validations.lock().unwrap();
+                validations.insert(check_id.clone(), tx);
+            }
+            let _pending = PendingEntry {
+                pending: &self.function_validations,
+                id: check_id.clone(),
+            };
+
+            let exists_request = FunctionExistsRequest {
+                id: check_id.clone(),
+                action: "is_function_exists".to_string(),
+                function_name: name.to_string(),
+            };
+
+            let request_json = serde_json::to_string(&exists_request)?;
+            (self.send_message)(request_json).await?;
+
+            let wait = self.timeouts.function_exists;
+            let function_exists = match timeout(wait, rx).await {
+                Ok(response_result) => match response_result {
+                    Ok(response) => {
+                        // Only answers are cached; a check that failed is
+                        // asked again next time.
+                        if let Some(cache) = &self.exists_cache {
+                            cache.lock().unwrap().insert(name.to_string(), response.exists);
+                        }
+                        response.exists
+                    }
+                    Err(_) => {
+                        debug!(
+                            "Function exists check response channel closed for '{}'",
+                            name
+                        );
+                        false
+                    }
+                },
+                Err(_) => {
+                    warn!(
+                        "Function exists check for '{}' timed out after {} ms",
+                        name,
+                        wait.as_millis()
+                    );
+                    return Err(timeout_error(
+                        name,
+                        format!(
+                            "Remote function '{}' did not answer the existence check within {} ms",
+                            name,
+                            wait.as_millis()
+                        ),
+                    ));
+                }
+            };
+
+            Ok(function_exists)
+        })
+    }
+
+    fn call_function<'a>(
+        &'a self,
+        name: &'a str,
+        arguments: Vec<Value>,
+        secret_context: Option<&'a Value>,
+    ) -> BoxFuture<'a, Result<Value, RuntimeError>> {
+        Box::pin(async move {
+            let call_id = Uuid::new_v4().to_string();
+
+            let (tx, rx) = oneshot::channel::<FunctionCallResponse>();
+
+            {
+                let mut calls = self.function_calls.lock().unwrap();
+                calls.insert(call_id.clone(), tx);
+            }
+            let _pending = PendingEntry {
+                pending: &self.function_calls,
+                id: call_id.clone(),
+            };
+
+            let request = FunctionCallRequest {
+                id: call_id.clone(),
+                function_name: name.to_string(),
+                arguments,
+                secret_context: secret_context.cloned(),
+            };
+
+            self.send_call(request).await?;
+
+            let wait = self
+                .call_timeouts
+                .get(name)
+                .copied()
+                .unwrap_or(self.timeouts.function_call);
+            match timeout(wait, rx).await {
+                Ok(response_result) => match response_result {
+                    Ok(response) => match response.error {
+                        Some(err) => Err(RuntimeError::FunctionCallError(err)),
+                        None => Ok(response.result),
+                    },
+                    Err(_) => Err(RuntimeError::ChannelError(
+                        "Function call response channel closed".to_string(),
+                    )),
+                },
+                Err(_) => {
+                    warn!("Function call '{}' timed out after {} ms", name, wait.as_millis());
+                    Err(timeout_error(
+                        name,
+                        format!(
+                            "Remote function '{}' did not respond within {} ms",
+                            name,
+                            wait.as_millis()
+                        ),
+                    ))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::FutureExt;
+
+    /// A host whose client answers existence checks, declaring only `remote`,
+    /// and records the names it was asked about.
+    fn host_with_client(checks: Arc<Mutex<Vec<String>>>) -> RemoteFunctionHost {
+        let function_validations = PendingFunctionValidations::default();
+        let validations = function_validations.clone();
+        let send_message: Arc<MessageSender> = Arc::new(move |message: String| {
+            let request: FunctionExistsRequest = serde_json::from_str(&message).unwrap();
+            checks.lock().unwrap().push(request.function_name.clone());
+            if let Some(tx) = validations.lock().unwrap().remove(&request.id) {
+                let _ = tx.send(FunctionExistsResponse {
+                    id: request.id,
+                    exists: request.function_name == "remote",
+                });
+            }
+            async { Ok(()) }.boxed()
+        });
+
+        RemoteFunctionHost::new(PendingFunctionCalls::default(), function_validations, send_message)
+    }
+
+    #[tokio::test]
+    async fn names_missing_from_the_manifest_are_checked_with_the_client() {
+        let checks = Arc::new(Mutex::new(Vec::new()));
+        let host = host_with_client(checks.clone())
+            .with_manifest(&[FunctionSignature::named("declared")]);
+
+        assert!(host.function_exists("declared").await.unwrap());
+        assert!(host.function_exists("remote").await.unwrap());
+        assert!(!host.function_exists("missing").await.unwrap());
+        assert_eq!(*checks.lock().unwrap(), ["remote", "missing"]);
+    }
+
+    #[tokio::test]
+    async fn cached_answers_are_shared_by_the_requests_of_a_connection() {
+        let checks = Arc::new(Mutex::new(Vec::new()));
+        let remote_functions = RemoteFunctions::default();
+
+        for _ in 0..2 {
+            let host = host_with_client(checks.clone()).with_exists_cache(remote_functions.exists_cache.clone());
+            assert!(host.function_exists("remote").await.unwrap());
+            assert!(!host.function_exists("missing").await.unwrap());
+        }
+        assert_eq!(*checks.lock().unwrap(), ["remote", "missing"]);
+
+        let host = host_with_client(checks.clone()).skip_existence_check();
+        assert!(host.function_exists("missing").await.unwrap());
+        assert_eq!(checks.lock().unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn unanswered_requests_time_out_after_the_configured_limits() {
+        let send_message: Arc<MessageSender> = Arc::new(|_| async { Ok(()) }.boxed());
+        let options = FunctionTimeoutOptions {
+            function_exists_ms: Some(10),
+            function_call_ms: None,
+        };
+        let timeouts = FunctionTimeouts::default().with_overrides(&options);
+        assert_eq!(timeouts.function_call, Duration::from_secs(600));
+
+        let mut slow = FunctionSignature::named("slow");
+        slow.timeout_ms = Some(20);
+        let host = RemoteFunctionHost::new(PendingFunctionCalls::default(), PendingFunctionValidations::default(), send_message)
+            .with_timeouts(timeouts)
+            .with_manifest(&[slow]);
+
+        let error = host.function_exists("missing").await.unwrap_err();
+        assert!(matches!(
+            &error,
+            RuntimeError::TimeoutError { function, message, .. } if function == "missing" && message.ends_with("within 10 ms")
+        ));
+        let error = host.call_function("slow", Vec::new(), None).await.unwrap_err();
+        assert!(matches!(
+            &error,
+            RuntimeError::TimeoutError { function, message, .. } if function == "slow" && message.ends_with("within 20 ms")
+        ));
+    }
+}
@@ -0,0 +1,102 @@
+use crate::error::RuntimeError;
+use crate::handler::{self, ExecutionEnv};
+use crate::host::FunctionHost;
+use crate::limits::{ExecutionLimits, ResourceTracker};
+use futures_util::future::BoxFuture;
+use hexput_ast_api::ast_structs::Program;
+use hexput_ast_api::feature_flags::FeatureFlags;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+pub type NativeFunction = Arc<dyn Fn(Vec<Value>) -> Result<Value, String> + Send + Sync>;
+
+/// Runs Hexput programs in-process, without a WebSocket client.
+///
+/// Calls to functions that are not callbacks defined by the script are
+/// resolved against the native functions registered on the interpreter.
+#[derive(Clone, Default)]
+pub struct Interpreter {
+    functions: HashMap<String, NativeFunction>,
+    feature_flags: FeatureFlags,
+    limits: ExecutionLimits,
+}
+
+impl Interpreter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the feature flags used by `execute_code` when parsing source.
+    pub fn with_feature_flags(mut self, feature_flags: FeatureFlags) -> Self {
+        self.feature_flags = feature_flags;
+        self
+    }
+
+    /// Sets the resource limits applied to every execution.
+    pub fn with_limits(mut self, limits: ExecutionLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn register_function<F>(&mut self, name: impl Into<String>, function: F) -> &mut Self
+    where
+        F: Fn(Vec<Value>) -> Result<Value, String> + Send + Sync + 'static,
+    {
+        self.functions.insert(name.into(), Arc::new(function));
+        self
+    }
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Executes an already parsed program with the given context variables.
+    ///
+    /// Returns the value of the top-level `res` statement, or `null` when the
+    /// program finishes without one.
+    pub async fn execute(&self, program: &Program, context: Map<String, Value>) -> Result<Value, RuntimeError> {
+        let env = ExecutionEnv {
+            secret_context: None,
+            host: self,
+            tracker: ResourceTracker::new(self.limits),
+        };
+
+        handler::run_program(program.statements.clone(), context, &env).await
+    }
+
+    /// Parses `code` with the interpreter's feature flags and executes it.
+    pub async fn execute_code(&self, code: &str, context: Map<String, Value>) -> Result<Value, RuntimeError> {
+        let code = code.to_string();
+        let feature_flags = self.feature_flags;
+
+        let program = tokio::task::spawn_blocking(move || hexput_ast_api::process_code(&code, feature_flags))
+            .await
+            .map_err(|e| RuntimeError::TaskExecutionError(format!("Task join error: {}", e)))?
+            .map_err(RuntimeError::ParseError)?;
+
+        self.execute(&program, context).await
+    }
+}
+
+impl FunctionHost for Interpreter {
+    fn function_exists<'a>(&'a self, name: &'a str) -> BoxFuture<'a, Result<bool, RuntimeError>> {
+        Box::pin(async move { Ok(self.has_function(name)) })
+    }
+
+    fn call_function<'a>(
+        &'a self,
+        name: &'a str,
+        arguments: Vec<Value>,
+        _secret_context: Option<&'a Value>,
+    ) -> BoxFuture<'a, Result<Value, RuntimeError>> {
+        Box::pin(async move {
+            let function = self
+                .functions
+                .get(name)
+                .ok_or_else(|| RuntimeError::FunctionNotFoundError(format!("Function '{}' not found", name)))?;
+
+            function(arguments).map_err(RuntimeError::FunctionCallError)
+        })
+    }
+}
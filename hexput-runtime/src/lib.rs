@@ -0,0 +1,13 @@
+pub mod error;
+pub mod handler;
+pub mod host;
+pub mod interpreter;
+pub mod limits;
+pub mod messages;
+pub mod range;
+pub mod server;
+pub mod builtins;
+
+pub use error::RuntimeError;
+pub use interpreter::Interpreter;
+pub use limits::ExecutionLimits;
@@ -0,0 +1,195 @@
+use crate::error::RuntimeError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// Iteration cap applied to each conditional or range loop when
+/// `max_loop_iterations` is not set, so that `loop while true { }` cannot
+/// run forever.
+pub const DEFAULT_MAX_LOOP_ITERATIONS: u64 = 1_000_000;
+
+/// Upper bounds on the resources a single execution may consume.
+///
+/// Every limit is optional; limits that are not set are not enforced,
+/// except for `max_loop_iterations` which falls back to
+/// `DEFAULT_MAX_LOOP_ITERATIONS`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct ExecutionLimits {
+    /// Maximum number of statements and loop iterations executed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_statements: Option<u64>,
+    /// Maximum nesting depth of callback calls.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_call_depth: Option<usize>,
+    /// Maximum size, in bytes of its JSON encoding, of any value built by the
+    /// script. Each value is checked on its own; this is not a cap on the
+    /// total memory of the execution, which `max_statements` bounds instead
+    /// by limiting how many values can be built.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_value_size: Option<usize>,
+    /// Wall-clock budget for the whole execution, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout_ms: Option<u64>,
+    /// Maximum number of iterations of a single conditional or range loop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_loop_iterations: Option<u64>,
+    /// Maximum number of elements of `all` or items of `parallelMap`
+    /// evaluated at the same time.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrency: Option<usize>,
+}
+
+impl ExecutionLimits {
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout_ms.map(Duration::from_millis)
+    }
+}
+
+/// Tracks resource usage of one execution against its `ExecutionLimits`.
+pub(crate) struct ResourceTracker {
+    limits: ExecutionLimits,
+    deadline: Option<Instant>,
+    statements: AtomicU64,
+}
+
+impl ResourceTracker {
+    pub(crate) fn new(limits: ExecutionLimits) -> Self {
+        Self {
+            limits,
+            deadline: limits.timeout().map(|timeout| Instant::now() + timeout),
+            statements: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn limits(&self) -> &ExecutionLimits {
+        &self.limits
+    }
+
+    /// Accounts for one executed statement or loop iteration and checks the deadline.
+    pub(crate) fn step(&self) -> Result<(), RuntimeError> {
+        let executed = self.statements.fetch_add(1, Ordering::Relaxed) + 1;
+
+        if let Some(max_statements) = self.limits.max_statements {
+            if executed > max_statements {
+                return Err(limit_error(format!(
+                    "Statement limit of {} exceeded",
+                    max_statements
+                )));
+            }
+        }
+
+        if let Some(deadline) = self.deadline {
+            if Instant::now() >= deadline {
+                return Err(self.timeout_error());
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks the iteration count of a conditional or range loop before running
+    /// iteration number `iteration` (counted from one).
+    pub(crate) fn check_loop_iteration(&self, iteration: u64) -> Result<(), RuntimeError> {
+        let max_iterations = self
+            .limits
+            .max_loop_iterations
+            .unwrap_or(DEFAULT_MAX_LOOP_ITERATIONS);
+
+        if iteration > max_iterations {
+            return Err(limit_error(format!(
+                "Loop iteration limit of {} exceeded",
+                max_iterations
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Checks the nesting depth of a callback call, counted from one for a
+    /// call made outside of any callback.
+    pub(crate) fn check_call_depth(&self, depth: usize) -> Result<(), RuntimeError> {
+        if let Some(max_call_depth) = self.limits.max_call_depth {
+            if depth > max_call_depth {
+                return Err(limit_error(format!(
+                    "Maximum call depth of {} exceeded",
+                    max_call_depth
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_value(&self, value: &Value) -> Result<(), RuntimeError> {
+        if let Some(max_value_size) = self.limits.max_value_size {
+            if encoded_size(value, max_value_size) > max_value_size {
+                return Err(limit_error(format!(
+                    "Value size limit of {} bytes exceeded",
+                    max_value_size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks `count` nulls before they are added to pad an array. Every
+    /// null takes five bytes (`null` and a separator) once encoded.
+    pub(crate) fn check_array_padding(&self, count: usize) -> Result<(), RuntimeError> {
+        if let Some(max_value_size) = self.limits.max_value_size {
+            if count.saturating_mul(5) > max_value_size {
+                return Err(limit_error(format!(
+                    "Value size limit of {} bytes exceeded",
+                    max_value_size
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn timeout_error(&self) -> RuntimeError {
+        limit_error(format!(
+            "Execution time limit of {} ms exceeded",
+            self.limits.timeout_ms.unwrap_or_default()
+        ))
+    }
+}
+
+fn limit_error(message: String) -> RuntimeError {
+    RuntimeError::ResourceLimitExceeded {
+        message,
+        location: None,
+    }
+}
+
+/// Size of the JSON encoding of `value`, counted up to just past `cap`.
+fn encoded_size(value: &Value, cap: usize) -> usize {
+    match value {
+        Value::Null => 4,
+        Value::Bool(b) => if *b { 4 } else { 5 },
+        Value::Number(n) => n.to_string().len(),
+        Value::String(s) => s.len() + 2,
+        Value::Array(items) => {
+            let mut size = 2 + items.len().saturating_sub(1);
+            for item in items {
+                if size > cap {
+                    break;
+                }
+                size += encoded_size(item, cap - size);
+            }
+            size
+        }
+        Value::Object(map) => {
+            let mut size = 2 + map.len().saturating_sub(1);
+            for (key, item) in map {
+                if size > cap {
+                    break;
+                }
+                size += key.len() + 3 + encoded_size(item, cap - size);
+            }
+            size
+        }
+    }
+}
@@ -1,10 +1,7 @@
-pub mod error;
-pub mod handler;
-pub mod messages;
-pub mod server;
-pub mod builtins;
-
 use clap::Parser;
+use hexput_runtime::host::FunctionTimeouts;
+use hexput_runtime::server;
+use std::time::Duration;
 use tracing::info;
 use tracing_subscriber::{FmtSubscriber, EnvFilter};
 
@@ -22,6 +19,14 @@ struct Args {
     
     #[arg(short, long, default_value = "info")]
     log_level: String,
+
+    /// How long to wait for the answer to an `is_function_exists` check, in milliseconds
+    #[arg(long, default_value = "3000")]
+    function_exists_timeout_ms: u64,
+
+    /// How long to wait for the result of a remote function call, in milliseconds
+    #[arg(long, default_value = "600000")]
+    function_call_timeout_ms: u64,
 }
 
 #[tokio::main]
@@ -62,6 +67,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let config = server::ServerConfig {
         address: server_address,
+        timeouts: FunctionTimeouts {
+            function_exists: Duration::from_millis(args.function_exists_timeout_ms),
+            function_call: Duration::from_millis(args.function_call_timeout_ms),
+        },
     };
 
     match server::run_server(config).await {
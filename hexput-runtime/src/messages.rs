@@ -1,9 +1,13 @@
+use crate::error::{CallFrame, RuntimeError};
+use crate::limits::ExecutionLimits;
+use hexput_ast_api::analysis::FunctionSignature;
 use hexput_ast_api::feature_flags::FeatureFlags;
+use hexput_ast_api::parser::ParseError;
 use serde::{Deserialize, Serialize, de::Deserializer};
 
 #[derive(Debug, Clone)]
 pub enum WebSocketMessage {
-    Request(WebSocketRequest),
+    Request(Box<WebSocketRequest>),
     FunctionResponse(FunctionCallResponse),
     FunctionExistsResponse(FunctionExistsResponse),
     Unknown(serde_json::Value)
@@ -31,7 +35,7 @@ impl<'de> Deserialize<'de> for WebSocketMessage {
             
             if map.contains_key("action") {
                 if let Ok(request) = serde_json::from_value::<WebSocketRequest>(value.clone()) {
-                    return Ok(WebSocketMessage::Request(request));
+                    return Ok(WebSocketMessage::Request(Box::new(request)));
                 }
             }
         }
@@ -44,13 +48,53 @@ impl<'de> Deserialize<'de> for WebSocketMessage {
 pub struct WebSocketRequest {
     pub id: String,
     pub action: String,
+    #[serde(default)]
     pub code: String,
+    /// A program produced by a `parse` request, run by `execute_ast`
+    /// instead of `code`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ast: Option<serde_json::Value>,
     #[serde(default)]
     pub options: AstParserOptions,
     #[serde(default)]
     pub context: serde_json::Map<String, serde_json::Value>,
+    /// The remote functions the host provides. When set, calls are checked
+    /// against it before execution, and the declared functions need no
+    /// `is_function_exists` round trip. Other names, such as remote methods,
+    /// are still checked with the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub functions: Option<Vec<FunctionSignature>>,
     #[serde(default, skip_serializing_if = "Option::is_none")]
     pub secret_context: Option<serde_json::Value>,
+    #[serde(default)]
+    pub limits: ExecutionLimits,
+    #[serde(default)]
+    pub timeouts: FunctionTimeoutOptions,
+}
+
+/// Overrides of the connection's remote function timeouts for one request.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct FunctionTimeoutOptions {
+    /// How long to wait for the answer to an `is_function_exists` check, in
+    /// milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_exists_ms: Option<u64>,
+    /// How long to wait for the result of a function call, in milliseconds.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub function_call_ms: Option<u64>,
+}
+
+impl WebSocketRequest {
+    /// Adds the functions registered on the connection, keeping the
+    /// signatures the request declares itself.
+    pub fn inherit_functions(&mut self, registered: &[FunctionSignature]) {
+        let functions = self.functions.get_or_insert_with(Vec::new);
+        for signature in registered {
+            if !functions.iter().any(|own| own.name == signature.name) {
+                functions.push(signature.clone());
+            }
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -60,7 +104,71 @@ pub struct WebSocketResponse {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub result: Option<serde_json::Value>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>
+    pub error: Option<ResponseError>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub cancelled: bool,
+}
+
+/// An error as sent to clients: a plain message, or a structured object when
+/// the request set the `structured_errors` option.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum ResponseError {
+    Details(ErrorDetails),
+    Message(String),
+}
+
+impl From<String> for ResponseError {
+    fn from(message: String) -> Self {
+        ResponseError::Message(message)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ErrorDetails {
+    pub kind: String,
+    pub message: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub location: Option<ErrorLocation>,
+    #[serde(default)]
+    pub stack: Vec<StackFrame>,
+}
+
+impl From<&RuntimeError> for ErrorDetails {
+    fn from(error: &RuntimeError) -> Self {
+        ErrorDetails {
+            kind: error.kind().to_string(),
+            message: error.message(),
+            location: error.location().map(ErrorLocation::from),
+            stack: error.call_stack().iter().map(StackFrame::from).collect(),
+        }
+    }
+}
+
+impl From<&ParseError> for ErrorDetails {
+    fn from(error: &ParseError) -> Self {
+        ErrorDetails {
+            kind: error.kind().to_string(),
+            message: error.message(),
+            location: Some(error.location().into()),
+            stack: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StackFrame {
+    pub name: String,
+    pub location: ErrorLocation,
+}
+
+impl From<&CallFrame> for StackFrame {
+    fn from(frame: &CallFrame) -> Self {
+        StackFrame {
+            name: frame.name.clone(),
+            location: frame.location.into(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -91,6 +199,15 @@ pub struct FunctionCallRequest {
     pub secret_context: Option<serde_json::Value>,
 }
 
+/// Function calls made at the same time, sent in one message to clients
+/// that accept batches. Each call is answered with its own
+/// `FunctionCallResponse`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FunctionCallBatchRequest {
+    pub id: String,
+    pub calls: Vec<FunctionCallRequest>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct FunctionCallResponse {
     pub id: String,
@@ -116,7 +233,7 @@ pub struct FunctionExistsResponse {
 pub struct ExecutionResult {
     pub value: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
-    pub error: Option<String>,
+    pub error: Option<ResponseError>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
@@ -126,6 +243,13 @@ pub struct AstParserOptions {
     #[serde(default)]
     pub include_source_mapping: bool,
     #[serde(default)]
+    pub structured_errors: bool,
+    /// Keeps comments in the AST returned by `parse`.
+    #[serde(default)]
+    pub include_comments: bool,
+    #[serde(default)]
+    pub function_cache: FunctionCacheScope,
+    #[serde(default)]
     pub no_object_constructions: bool,
     #[serde(default)]
     pub no_array_constructions: bool,
@@ -136,6 +260,8 @@ pub struct AstParserOptions {
     #[serde(default)]
     pub no_loops: bool,
     #[serde(default)]
+    pub no_while_loops: bool,
+    #[serde(default)]
     pub no_object_keys: bool,
     #[serde(default)]
     pub no_callbacks: bool,
@@ -153,10 +279,28 @@ pub struct AstParserOptions {
     pub no_assignments: bool,
 }
 
+/// How long the answers of `is_function_exists` checks are reused.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FunctionCacheScope {
+    /// Shared by every request on the connection until the client
+    /// invalidates them.
+    #[default]
+    Connection,
+    /// Only reused within the request.
+    Request,
+    /// Every call asks again.
+    Disabled,
+}
+
 fn default_true() -> bool {
     true
 }
 
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
 impl AstParserOptions {
     pub fn to_feature_flags(&self) -> FeatureFlags {
         FeatureFlags {
@@ -165,6 +309,7 @@ impl AstParserOptions {
             allow_object_navigation: !self.no_object_navigation,
             allow_variable_declaration: !self.no_variable_declaration,
             allow_loops: !self.no_loops,
+            allow_while_loops: !self.no_while_loops,
             allow_object_keys: !self.no_object_keys,
             allow_callbacks: !self.no_callbacks,
             allow_conditionals: !self.no_conditionals,
@@ -0,0 +1,100 @@
+use serde_json::{Number, Value};
+
+const RANGE_REFERENCE_HASH: &str = "__range_constant";
+
+/// A numeric range produced by `start..end`, `start..=end` or
+/// `start..end step n`.
+///
+/// A range written as the iterable of a loop is expanded one number at a
+/// time. One passed to `slice` or `substring` reaches the built-in method
+/// as a marker object, like callback references. Anywhere else it is built
+/// as an array of its numbers, so scripts never see the marker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RangeValue {
+    pub start: f64,
+    pub end: f64,
+    pub step: f64,
+    pub inclusive: bool,
+}
+
+impl RangeValue {
+    /// Builds a range, rejecting non-finite bounds and a zero step.
+    pub fn new(start: f64, end: f64, step: f64, inclusive: bool) -> Result<Self, String> {
+        if !start.is_finite() || !end.is_finite() || !step.is_finite() {
+            return Err("Range bounds and step must be finite numbers".to_string());
+        }
+        if step == 0.0 {
+            return Err("Range step cannot be zero".to_string());
+        }
+
+        Ok(Self { start, end, step, inclusive })
+    }
+
+    /// Reads a range back from its marker object.
+    pub fn from_value(value: &Value) -> Option<Self> {
+        let map = value.as_object()?;
+
+        if map.get("type").and_then(Value::as_str) != Some("range")
+            || map.get("hash").and_then(Value::as_str) != Some(RANGE_REFERENCE_HASH)
+        {
+            return None;
+        }
+
+        Self::new(
+            map.get("start")?.as_f64()?,
+            map.get("end")?.as_f64()?,
+            map.get("step")?.as_f64()?,
+            map.get("inclusive")?.as_bool()?,
+        )
+        .ok()
+    }
+
+    pub fn to_value(&self) -> Value {
+        serde_json::json!({
+            "type": "range",
+            "start": self.start,
+            "end": self.end,
+            "step": self.step,
+            "inclusive": self.inclusive,
+            "hash": RANGE_REFERENCE_HASH
+        })
+    }
+
+    /// Iterates the numbers of the range without materializing them.
+    pub fn iter(&self) -> RangeIter {
+        RangeIter { range: *self, index: 0 }
+    }
+}
+
+pub struct RangeIter {
+    range: RangeValue,
+    index: u64,
+}
+
+impl Iterator for RangeIter {
+    type Item = f64;
+
+    fn next(&mut self) -> Option<f64> {
+        // Computed from the index rather than accumulated, so fractional
+        // steps do not drift.
+        let current = self.range.start + self.index as f64 * self.range.step;
+
+        let in_range = match (self.range.step > 0.0, self.range.inclusive) {
+            (true, false) => current < self.range.end,
+            (true, true) => current <= self.range.end,
+            (false, false) => current > self.range.end,
+            (false, true) => current >= self.range.end,
+        };
+
+        if !in_range {
+            return None;
+        }
+
+        self.index += 1;
+        Some(current)
+    }
+}
+
+pub fn number_value(number: f64) -> Value {
+    Value::Number(Number::from_f64(number).unwrap_or(Number::from(0)))
+}
@@ -1,17 +1,30 @@
 use crate::error::RuntimeError;
+use crate::host::{FunctionExistsCache, FunctionTimeouts, RemoteFunctions, PendingFunctionCalls, PendingFunctionValidations};
 use crate::messages::{FunctionCallResponse, FunctionExistsResponse, WebSocketMessage, WebSocketRequest, WebSocketResponse};
 use futures_util::{SinkExt, StreamExt};
+use hexput_ast_api::analysis::FunctionSignature;
 use std::net::SocketAddr;
 use std::sync::{Arc, Mutex};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::{mpsc, Mutex as TokioMutex, oneshot};
+use tokio::task::AbortHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
 use tokio_tungstenite::tungstenite::Message;
 use tracing::{debug, error, info};
 use std::collections::HashMap;
 use serde_json::json;
 
+/// Sent in the handshake by clients that handle every function a script
+/// calls, so that no `is_function_exists` checks are made.
+const SKIP_FUNCTION_EXISTS: &str = "skip_function_exists";
+/// Sent by clients that accept several function calls in one message.
+const BATCH_FUNCTION_CALLS: &str = "batch_function_calls";
+const SUPPORTED_CAPABILITIES: [&str; 2] = [SKIP_FUNCTION_EXISTS, BATCH_FUNCTION_CALLS];
+
 pub struct ServerConfig {
     pub address: String,
+    /// Remote function timeouts of requests that do not set their own.
+    pub timeouts: FunctionTimeouts,
 }
 
 pub async fn run_server(config: ServerConfig) -> Result<(), RuntimeError> {
@@ -23,6 +36,7 @@ pub async fn run_server(config: ServerConfig) -> Result<(), RuntimeError> {
     info!("WebSocket server listening on: {}", addr);
 
     let active_connections = Arc::new(TokioMutex::new(0));
+    let timeouts = config.timeouts;
 
     while let Ok((stream, peer_addr)) = listener.accept().await {
         info!("New connection from: {}", peer_addr);
@@ -36,7 +50,7 @@ pub async fn run_server(config: ServerConfig) -> Result<(), RuntimeError> {
         }
 
         tokio::spawn(async move {
-            match handle_connection(stream, peer_addr).await {
+            match handle_connection(stream, peer_addr, timeouts).await {
                 Ok(_) => info!("Connection from {} closed gracefully", peer_addr),
                 Err(e) => error!("Error handling connection from {}: {}", peer_addr, e),
             }
@@ -56,11 +70,29 @@ enum SenderMessage {
     Close,
 }
 
-async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr) -> Result<(), RuntimeError> {
+async fn handle_connection(
+    stream: TcpStream,
+    peer_addr: SocketAddr,
+    timeouts: FunctionTimeouts,
+) -> Result<(), RuntimeError> {
     debug!("Starting WebSocket handshake with: {}", peer_addr);
-    let ws_stream = tokio_tungstenite::accept_async(stream).await?;
+    let mut capabilities = Vec::new();
+    // The error type of the callback is set by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let read_capabilities = |request: &Request, response: Response| {
+        capabilities = requested_capabilities(request);
+        Ok(response)
+    };
+    let ws_stream = tokio_tungstenite::accept_hdr_async(stream, read_capabilities).await?;
     info!("WebSocket connection established with: {}", peer_addr);
 
+    let remote_functions = RemoteFunctions {
+        skip_existence_check: capabilities.iter().any(|capability| capability == SKIP_FUNCTION_EXISTS),
+        batch_calls: capabilities.iter().any(|capability| capability == BATCH_FUNCTION_CALLS),
+        timeouts,
+        ..RemoteFunctions::default()
+    };
+
     let (ws_sender, mut ws_receiver) = ws_stream.split();
     
     let (sender_tx, mut sender_rx) = mpsc::channel::<SenderMessage>(100);
@@ -96,14 +128,20 @@ async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr) -> Result<(
     
     let welcome_sender = sender_tx.clone();
     
-    if let Err(e) = welcome_sender.send(SenderMessage::Text(
-        r#"{"type":"connection","status":"connected"}"#.to_string()
-    )).await {
+    let welcome_message = json!({
+        "type": "connection",
+        "status": "connected",
+        "capabilities": capabilities,
+    });
+    
+    if let Err(e) = welcome_sender.send(SenderMessage::Text(welcome_message.to_string())).await {
         error!("Failed to send welcome message: {}", e);
         return Err(RuntimeError::ConnectionError("Failed to send welcome message".to_string()));
     }
 
     let mut task_set: tokio::task::JoinSet<()> = tokio::task::JoinSet::new();
+    let mut running_requests: HashMap<String, AbortHandle> = HashMap::new();
+    let mut registered_functions: Option<Vec<FunctionSignature>> = None;
 
     let create_message_sender = |tx: mpsc::Sender<SenderMessage>| {
         move |message: String| -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> {
@@ -140,12 +178,62 @@ async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr) -> Result<(
                             error!("Error processing function exists response: {}", e);
                         }
                     },
-                    Ok(WebSocketMessage::Request(request)) => {
+                    Ok(WebSocketMessage::Request(request)) if request.action == "cancel" => {
+                        debug!("Received cancel request for ID: {}", request.id);
+
+                        let response = cancel_request(&request.id, &mut running_requests);
+
+                        match serde_json::to_string(&response) {
+                            Ok(json) => {
+                                if let Err(e) = sender_clone.send(SenderMessage::Text(json)).await {
+                                    error!("Failed to send cancel response: {}", e);
+                                }
+                            }
+                            Err(e) => error!("Failed to serialize cancel response: {}", e),
+                        }
+                    },
+                    Ok(WebSocketMessage::Request(request)) if request.action == "register_functions" => {
+                        debug!("Received function manifest with ID: {}", request.id);
+
+                        let response = register_functions(*request, &mut registered_functions);
+
+                        match serde_json::to_string(&response) {
+                            Ok(json) => {
+                                if let Err(e) = sender_clone.send(SenderMessage::Text(json)).await {
+                                    error!("Failed to send register response: {}", e);
+                                }
+                            }
+                            Err(e) => error!("Failed to serialize register response: {}", e),
+                        }
+                    },
+                    Ok(WebSocketMessage::Request(request)) if request.action == "invalidate_functions" => {
+                        debug!("Received function cache invalidation with ID: {}", request.id);
+
+                        let response = invalidate_functions(*request, &remote_functions.exists_cache);
+
+                        match serde_json::to_string(&response) {
+                            Ok(json) => {
+                                if let Err(e) = sender_clone.send(SenderMessage::Text(json)).await {
+                                    error!("Failed to send invalidate response: {}", e);
+                                }
+                            }
+                            Err(e) => error!("Failed to serialize invalidate response: {}", e),
+                        }
+                    },
+                    Ok(WebSocketMessage::Request(mut request)) => {
                         debug!("Processing request with ID: {}", request.id);
+                        if let Some(registered) = &registered_functions {
+                            request.inherit_functions(registered);
+                        }
                         let req_id = request.id.clone();
+                        let request_id = req_id.clone();
+                        let structured_errors = request.options.structured_errors;
+                        let remote_functions_clone = remote_functions.clone();
+
+                        running_requests.retain(|_, handle| !handle.is_finished());
                         
-                        task_set.spawn(async move {
-                            match process_request(request, function_calls_clone, function_validations_clone, message_sender).await {
+                        let abort_handle = task_set.spawn(async move {
+                            match process_request(*request, function_calls_clone, function_validations_clone, remote_functions_clone, message_sender).await {
                                 Ok(_) => debug!("Request {} processed successfully", req_id),
                                 Err(e) => {
                                     error!("Error processing request {}: {}", req_id, e);
@@ -154,7 +242,12 @@ async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr) -> Result<(
                                         id: req_id,
                                         success: false,
                                         result: None,
-                                        error: Some(format!("Internal error: {}", e)),
+                                        error: Some(if structured_errors {
+                                            crate::handler::runtime_error_response(&e, true)
+                                        } else {
+                                            format!("Internal error: {}", e).into()
+                                        }),
+                                        cancelled: false,
                                     };
                                     
                                     if let Ok(json) = serde_json::to_string(&error_response) {
@@ -165,6 +258,8 @@ async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr) -> Result<(
                                 }
                             }
                         });
+
+                        running_requests.insert(request_id, abort_handle);
                     },
                     Ok(WebSocketMessage::Unknown(value)) => {
                         error!("Received unknown message type: {}", value);
@@ -227,20 +322,102 @@ async fn handle_connection(stream: TcpStream, peer_addr: SocketAddr) -> Result<(
     Ok(())
 }
 
+/// Aborts the running task for `id`. Dropping the task also drops the
+/// pending remote function calls it registered.
+fn cancel_request(id: &str, running_requests: &mut HashMap<String, AbortHandle>) -> WebSocketResponse {
+    match running_requests.remove(id) {
+        Some(handle) if !handle.is_finished() => {
+            handle.abort();
+            info!("Cancelled request {}", id);
+
+            WebSocketResponse {
+                id: id.to_string(),
+                success: false,
+                result: None,
+                error: Some("Request cancelled".to_string().into()),
+                cancelled: true,
+            }
+        }
+        _ => WebSocketResponse {
+            id: id.to_string(),
+            success: false,
+            result: None,
+            error: Some(format!("No running request with ID: {}", id).into()),
+            cancelled: false,
+        },
+    }
+}
+
+/// Replaces the functions declared for every later request on the
+/// connection. A request without `functions` clears them, so that calls are
+/// checked with `is_function_exists` again.
+fn register_functions(request: WebSocketRequest, registered_functions: &mut Option<Vec<FunctionSignature>>) -> WebSocketResponse {
+    let count = request.functions.as_ref().map_or(0, Vec::len);
+    *registered_functions = request.functions;
+    info!("Registered {} functions for the connection", count);
+
+    WebSocketResponse {
+        id: request.id,
+        success: true,
+        result: Some(json!({ "registered": count })),
+        error: None,
+        cancelled: false,
+    }
+}
+
+/// Forgets cached existence answers: those of the functions named in the
+/// request, or all of them when it names none.
+fn invalidate_functions(request: WebSocketRequest, exists_cache: &FunctionExistsCache) -> WebSocketResponse {
+    let mut cache = exists_cache.lock().unwrap();
+    let invalidated = match &request.functions {
+        Some(functions) => functions
+            .iter()
+            .filter(|signature| cache.remove(&signature.name).is_some())
+            .count(),
+        None => cache.drain().count(),
+    };
+    debug!("Invalidated {} cached function existence answers", invalidated);
+
+    WebSocketResponse {
+        id: request.id,
+        success: true,
+        result: Some(json!({ "invalidated": invalidated })),
+        error: None,
+        cancelled: false,
+    }
+}
+
+/// Reads the `capabilities` query parameter of the handshake request, as in
+/// `ws://127.0.0.1:9001/?capabilities=skip_function_exists`, keeping the
+/// supported ones.
+fn requested_capabilities(request: &Request) -> Vec<String> {
+    request
+        .uri()
+        .query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter_map(|pair| pair.strip_prefix("capabilities="))
+        .flat_map(|value| value.split(','))
+        .filter(|capability| SUPPORTED_CAPABILITIES.contains(capability))
+        .map(str::to_string)
+        .collect()
+}
+
 async fn process_request(
     request: WebSocketRequest,
-    function_calls: Arc<Mutex<HashMap<String, oneshot::Sender<FunctionCallResponse>>>>,
-    function_validations: Arc<Mutex<HashMap<String, oneshot::Sender<FunctionExistsResponse>>>>,
-    message_sender: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Clone + 'static,
+    function_calls: PendingFunctionCalls,
+    function_validations: PendingFunctionValidations,
+    remote_functions: RemoteFunctions,
+    message_sender: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + Clone + 'static,
 ) -> Result<(), RuntimeError> {
-    handle_request(request, function_calls, function_validations, message_sender).await?;
+    handle_request(request, function_calls, function_validations, remote_functions, message_sender).await?;
     
     Ok(())
 }
 
 async fn handle_function_response_directly(
     response: FunctionCallResponse,
-    function_calls: Arc<Mutex<HashMap<String, oneshot::Sender<FunctionCallResponse>>>>,
+    function_calls: PendingFunctionCalls,
 ) -> Result<(), RuntimeError> {
     debug!("Processing function response for call ID: {}", response.id);
     
@@ -264,7 +441,7 @@ async fn handle_function_response_directly(
 
 async fn handle_function_exists_response(
     response: crate::messages::FunctionExistsResponse,
-    function_validations: Arc<Mutex<HashMap<String, oneshot::Sender<FunctionExistsResponse>>>>,
+    function_validations: PendingFunctionValidations,
 ) -> Result<(), RuntimeError> {
     debug!("Processing function exists response for ID: {}", response.id);
     
@@ -288,11 +465,12 @@ async fn handle_function_exists_response(
 
 async fn handle_request(
     request: WebSocketRequest,
-    function_calls: Arc<Mutex<HashMap<String, oneshot::Sender<FunctionCallResponse>>>>,
-    function_validations: Arc<Mutex<HashMap<String, oneshot::Sender<FunctionExistsResponse>>>>,
-    message_sender: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Clone + 'static,
+    function_calls: PendingFunctionCalls,
+    function_validations: PendingFunctionValidations,
+    remote_functions: RemoteFunctions,
+    message_sender: impl Fn(String) -> futures_util::future::BoxFuture<'static, Result<(), RuntimeError>> + Send + Sync + Clone + 'static,
 ) -> Result<(), RuntimeError> {
-    let result = crate::handler::handle_request(request, function_calls, function_validations, message_sender.clone()).await?;
+    let result = crate::handler::handle_request(request, function_calls, function_validations, remote_functions, message_sender.clone()).await?;
     
     if !result.is_empty() {
         message_sender(result).await?;
@@ -300,3 +478,27 @@ async fn handle_request(
     
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::ResponseError;
+
+    #[tokio::test]
+    async fn cancel_aborts_only_running_requests() {
+        let task = tokio::spawn(std::future::pending::<()>());
+        let mut running_requests = HashMap::new();
+        running_requests.insert("req-1".to_string(), task.abort_handle());
+
+        let response = cancel_request("req-1", &mut running_requests);
+        assert!(response.cancelled);
+        assert!(task.await.unwrap_err().is_cancelled());
+
+        let response = cancel_request("req-1", &mut running_requests);
+        assert!(!response.cancelled);
+        assert!(matches!(
+            response.error,
+            Some(ResponseError::Message(message)) if message == "No running request with ID: req-1"
+        ));
+    }
+}